@@ -1,34 +1,672 @@
 use super::verify_file;
+use crate::{
+    process::{
+        process_csv, process_csv_agg, process_csv_diff, process_csv_dump, process_csv_from,
+        process_csv_infer_schema, process_csv_join, process_csv_show, process_csv_sql,
+        process_csv_stats, process_csv_validate,
+    },
+    CmdExcuter,
+};
 use std::{
     fmt::{self, Display},
+    path::Path,
     str::FromStr,
 };
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
 
+/// `rcli csv -i input.csv` 直接转换, 其它功能用子命令, eg: `rcli csv from -i input.json`
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CsvOpts {
-    #[arg(short, long, value_parser=verify_file)]
-    pub input: String,
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+    // 不能用Option<CsvConvertOpts>: 里面还有flatten的时候clap判断不出参数是否存在, 永远是None
+    #[command(flatten)]
+    pub convert: CsvConvertOpts,
+}
+
+#[derive(Debug, Parser)]
+pub enum CsvSubCommand {
+    #[clap(
+        name = "from",
+        about = "Convert json/yaml/toml/ndjson back to csv, nested objects are flattened"
+    )]
+    From(CsvFromOpts),
+    #[clap(
+        name = "stats",
+        about = "Profile each column: type, nulls, distinct, min/max, mean and top values"
+    )]
+    Stats(CsvStatsOpts),
+    #[clap(name = "show", about = "Show csv as an aligned table in terminal")]
+    Show(CsvShowOpts),
+    #[clap(
+        name = "sql",
+        about = "Run sql on csv files, each file is loaded as a table of an in-memory sqlite"
+    )]
+    Sql(CsvSqlOpts),
+    #[clap(
+        name = "validate",
+        about = "Validate csv with a schema file, exit non-zero if any violation is found"
+    )]
+    Validate(CsvValidateOpts),
+    #[clap(
+        name = "infer-schema",
+        about = "Infer a schema from csv, as a yaml column spec (used by validate) or a JSON Schema"
+    )]
+    InferSchema(CsvInferSchemaOpts),
+    #[clap(
+        name = "join",
+        about = "Join two csv files on a key column, the smaller file is loaded into a hash table, the rows follow the order of the larger file"
+    )]
+    Join(CsvJoinOpts),
+    #[clap(
+        name = "diff",
+        about = "Compare two csv snapshots by key: added rows, removed rows and changed cells"
+    )]
+    Diff(CsvDiffOpts),
+    #[clap(
+        name = "agg",
+        about = "Group by columns and aggregate, --pivot turns the distinct values of a column into columns"
+    )]
+    Agg(CsvAggOpts),
+    #[clap(
+        name = "dump",
+        about = "Dump csv as sql: CREATE TABLE and batched INSERT statements, or into a sqlite database file"
+    )]
+    Dump(CsvDumpOpts),
+}
+
+impl CmdExcuter for CsvOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        match self.cmd {
+            Some(cmd) => cmd.execute().await?,
+            None => {
+                let opts = self.convert;
+                let output = if let Some(output) = &opts.output {
+                    output.clone()
+                } else if opts.input.as_deref() == Some("-") {
+                    "-".to_string()
+                } else {
+                    format!("output.{}", opts.format)
+                };
+                eprintln!("opts: {:?}", &opts);
+                process_csv(&opts, &output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CmdExcuter for CsvSubCommand {
+    async fn execute(self) -> anyhow::Result<()> {
+        match self {
+            CsvSubCommand::From(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let output = opts.output.clone().unwrap_or("output.csv".to_string());
+                process_csv_from(&opts, &output)?;
+            }
+            CsvSubCommand::Stats(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let report = process_csv_stats(&opts)?;
+                print!("{}", report);
+            }
+            CsvSubCommand::Show(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let table = process_csv_show(&opts)?;
+                print!("{}", table);
+            }
+            CsvSubCommand::Sql(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                process_csv_sql(&opts)?;
+            }
+            CsvSubCommand::Validate(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let (report, violations) = process_csv_validate(&opts)?;
+                print!("{}", report);
+                if violations > 0 {
+                    anyhow::bail!("{} violations found", violations);
+                }
+            }
+            CsvSubCommand::InferSchema(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let schema = process_csv_infer_schema(&opts)?;
+                print!("{}", schema);
+            }
+            CsvSubCommand::Join(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                process_csv_join(&opts)?;
+            }
+            CsvSubCommand::Diff(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                let report = process_csv_diff(&opts)?;
+                print!("{}", report);
+            }
+            CsvSubCommand::Agg(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                process_csv_agg(&opts)?;
+            }
+            CsvSubCommand::Dump(opts) => {
+                eprintln!("opts: {:?}", &opts);
+                process_csv_dump(&opts)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvConvertOpts {
+    /// 使用子命令的时候不需要, 所以是Option. '-'代表从标准输入读取, 也可以是xlsx, xls, ods表格文件
+    #[arg(short, long, value_parser=verify_file, required = true)]
+    pub input: Option<String>,
+    /// 表格文件的工作表, 名字或者从0开始的序号, 默认第一个
+    #[arg(long)]
+    pub sheet: Option<String>,
+    /// 表格文件的单元格范围, eg: A1:D20, B3表示从B3读到最后
+    #[arg(long)]
+    pub range: Option<String>,
     /// default_value默认值，传字符串然后由Parser convert
+    /// '-'代表输出到标准输出, 默认 output.{format}, 从标准输入读取时默认输出到标准输出
     #[arg(short, long, /*default_value = "output.json"*/)]
     pub output: Option<String>,
 
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 输出文件的格式, optional: [json, yaml, toml, ndjson, csv, tsv, parquet, arrow, html, markdown]
+    #[arg(long,value_parser=parse_format, default_value = "json")]
+    pub format: OutputFormat,
+    /// csv格式输出时的分隔符, tsv固定使用'\t'
+    #[arg(long, default_value_t = ',')]
+    pub out_delimiter: char,
+    #[command(flatten)]
+    pub writer: WriterOpts,
+    /// 输出文件的编码, eg: gbk, shift_jis, 默认utf-8
+    #[arg(long, value_parser = parse_encoding)]
+    pub out_encoding: Option<&'static Encoding>,
+    /// 每一行输出为数组而不是对象, eg: [["a", 1], ["b", 2]]
+    #[arg(long, default_value_t = false, conflicts_with = "nested")]
+    pub as_arrays: bool,
+    /// 推断每一列的类型(integer, float, boolean, null), 默认所有值都输出为string, parquet和arrow总是推断
+    #[arg(long, default_value_t = false)]
+    pub infer: bool,
+    /// 指定某一列的类型, eg: --type "Kit Number=string", 可以使用多次
+    /// optional types: [null, boolean, integer, float, string]
+    #[arg(long = "type", value_name = "COLUMN=TYPE", value_parser = parse_column_type)]
+    pub types: Vec<(String, ColumnType)>,
+    /// 把 `address.city`, `tags[0]` 这样的表头展开成嵌套的object和array
+    #[arg(long, default_value_t = false)]
+    pub nested: bool,
+    /// 过滤行, eg: --where "Position == 'Goalkeeper' && Kit Number > 10"
+    #[arg(long = "where")]
+    pub filter: Option<String>,
+    /// 只输出这些列, eg: --select Name,Nationality
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+    /// yaml映射文件: 重命名, 删除, 转换类型, 解析日期, 派生列和默认值
+    #[arg(long, value_parser = verify_file, conflicts_with = "select")]
+    pub mapping: Option<String>,
+    /// 排序, 默认升序, eg: --sort-by Nationality,Kit Number:desc
+    /// 数字按大小排在前面, 其他的值按字符串排序, 日期只有ISO格式(eg: 1990-04-18)才按时间排序
+    #[arg(long, value_delimiter = ',', value_parser = parse_sort_key)]
+    pub sort_by: Vec<SortKey>,
+    /// 多线程并行转换, 适合很大的文件, 输出和单线程转换完全一样
+    #[arg(long, default_value_t = false)]
+    pub parallel: bool,
+    /// 并行转换的线程数, 默认使用所有的CPU核
+    #[arg(long, requires = "parallel")]
+    pub jobs: Option<usize>,
+    /// 用chacha20加密这些输出列, 每个值使用随机的nonce, eg: --encrypt-columns Name,DOB
+    #[arg(long, value_delimiter = ',', requires = "encrypt_key")]
+    pub encrypt_columns: Vec<String>,
+    /// 解密 --encrypt-columns 加密的列, 和 --infer 一起使用时解密后推断类型
+    #[arg(
+        long,
+        value_delimiter = ',',
+        requires = "encrypt_key",
+        conflicts_with = "encrypt_columns"
+    )]
+    pub decrypt_columns: Vec<String>,
+    /// chacha20的key文件, 由 `rcli text generate --format chacha20` 生成
+    #[arg(long, value_parser = verify_file)]
+    pub encrypt_key: Option<String>,
+    /// 用keyed blake3 hash替换这些输出列的值, 相同的值得到相同的token, 可以用来join和group by
+    #[arg(long, value_delimiter = ',', requires = "token_key")]
+    pub tokenize_columns: Vec<String>,
+    /// blake3的key文件, 由 `rcli text generate --format blake3` 生成
+    #[arg(long, value_parser = verify_file)]
+    pub token_key: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub column: String,
+    pub desc: bool,
+}
+
+/// `count` or `FUNC(COLUMN)`, eg: `avg(Kit Number)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggSpec {
+    pub func: AggFunc,
+    pub column: Option<String>,
+}
+
+/// csv reader options shared by all csv subcommands
+#[derive(Debug, Clone, Parser)]
+pub struct CsvReaderOpts {
     #[arg(short, long, default_value_t = ',')]
     pub delimiter: char,
-    /// default_value_t不需要转换直接写rust的类型
-    #[arg(long, default_value_t = true)]
+    /// 文件没有表头, 第一行也是数据, 列名默认是col1, col2, ...
+    #[arg(long = "no-header", action = ArgAction::SetFalse)]
     pub header: bool,
-    /// 输出文件的格式
-    #[arg(long,value_parser=parse_format, default_value = "json")]
+    /// 指定列名, 没有表头的时候作为表头, 有表头的时候替换原来的表头, eg: --columns id,name
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+    /// 输入文件的编码, eg: gbk, shift_jis, utf-16le, 默认根据BOM和内容自动检测
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+    /// 引号字符
+    #[arg(long, default_value_t = '"')]
+    pub quote: char,
+    /// 引号内的转义字符, eg: --escape '\', 默认不转义
+    #[arg(long)]
+    pub escape: Option<char>,
+    /// 引号内的两个引号不再当作一个引号, 一般和--escape一起使用
+    #[arg(long = "no-double-quote", action = ArgAction::SetFalse)]
+    pub double_quote: bool,
+    /// 以这个字符开头的行是注释, 会被忽略, eg: --comment '#'
+    #[arg(long)]
+    pub comment: Option<char>,
+    /// 去掉两边的空白, optional: [none, headers, fields, all]
+    #[arg(long, value_parser = parse_trim_mode, default_value = "none")]
+    pub trim: TrimMode,
+    /// 列数和表头不一致的行, optional: [reject, fill]
+    /// reject: 报错, fill: 缺少的列补空值, 多出的列丢弃
+    #[arg(long, value_parser = parse_ragged_policy, default_value = "reject")]
+    pub ragged: RaggedPolicy,
+}
+
+impl Default for CsvReaderOpts {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            header: true,
+            columns: vec![],
+            encoding: None,
+            quote: '"',
+            escape: None,
+            double_quote: true,
+            comment: None,
+            trim: TrimMode::None,
+            ragged: RaggedPolicy::Reject,
+        }
+    }
+}
+
+/// options of the output formats, eg: parquet, arrow and html
+#[derive(Debug, Clone, Parser)]
+pub struct WriterOpts {
+    /// parquet每个row group(arrow每个record batch)的行数, 每列的类型由所有的值决定
+    #[arg(long, default_value_t = 65536, value_parser = clap::value_parser!(u64).range(1..))]
+    pub row_group_size: u64,
+    /// 压缩方式, parquet默认snappy, arrow默认none且只支持lz4和zstd
+    /// optional: [none, snappy, gzip, zstd, lz4]
+    #[arg(long, value_parser = parse_compression)]
+    pub compression: Option<Compression>,
+    /// html输出时table的css class, eg: --html-class "table table-striped"
+    #[arg(long)]
+    pub html_class: Option<String>,
+}
+
+impl Default for WriterOpts {
+    fn default() -> Self {
+        Self {
+            row_group_size: 65536,
+            compression: None,
+            html_class: None,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 每一列显示出现次数最多的前N个值
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+    /// use approximate distinct count and top values, memory is bounded for large files
+    #[arg(long, default_value_t = false)]
+    pub approx: bool,
+    /// optional: [table, json]
+    #[arg(long, value_parser = parse_report_format, default_value = "table")]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvShowOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 只显示前N行
+    #[arg(long, conflicts_with_all = ["tail", "range"])]
+    pub head: Option<usize>,
+    /// 只显示最后N行
+    #[arg(long, conflicts_with = "range")]
+    pub tail: Option<usize>,
+    /// 显示的行范围, 从0开始, 左闭右开, eg: 10..20, 10.., ..20
+    #[arg(long, value_parser = parse_row_range)]
+    pub range: Option<(usize, Option<usize>)>,
+    /// 只显示这些列, eg: --select Name,Nationality
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+    /// 每一列的最大显示宽度, 超出的部分用'…'截断
+    #[arg(long)]
+    pub max_width: Option<usize>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvSqlOpts {
+    /// sql语句, 表名默认是文件名(不带后缀), eg: "SELECT Nationality, count(*) FROM juventus GROUP BY 1"
+    pub query: String,
+    /// 作为表加载的csv文件, 可以使用多次, 格式: `path` 或者 `table=path`
+    #[arg(short, long, required = true, value_parser = parse_sql_table)]
+    pub input: Vec<(String, String)>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 输出文件路径, 默认输出到标准输出
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    /// 输出的格式, optional: [json, yaml, toml, ndjson, csv, tsv, parquet, arrow, html, markdown]
+    #[arg(long, value_parser = parse_format, default_value = "json")]
+    pub format: OutputFormat,
+    /// csv格式输出时的分隔符
+    #[arg(long, default_value_t = ',')]
+    pub out_delimiter: char,
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    /// 需要校验的csv文件
+    #[arg(value_parser = verify_file)]
+    pub input: String,
+    /// schema文件(yaml), 声明每一列的类型, 是否必填, 正则, 枚举值, 数值范围和唯一性
+    #[arg(short, long, value_parser = verify_file)]
+    pub schema: String,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// optional: [table, json]
+    #[arg(long, value_parser = parse_report_format, default_value = "table")]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvInferSchemaOpts {
+    /// 需要推断schema的csv文件
+    #[arg(value_parser = verify_file)]
+    pub input: String,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 只扫描前N行, 默认扫描整个文件
+    #[arg(long)]
+    pub sample: Option<usize>,
+    /// 不同值的个数不超过N的字符串列输出为enum, 0表示不输出enum
+    #[arg(long, default_value_t = 10)]
+    pub max_enum: usize,
+    /// optional: [yaml, json-schema], yaml can be used by `rcli csv validate --schema`
+    #[arg(long, value_parser = parse_schema_format, default_value = "yaml")]
+    pub format: SchemaFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    /// 左边的csv文件
+    #[arg(value_parser = verify_file)]
+    pub left: String,
+    /// 右边的csv文件
+    #[arg(value_parser = verify_file)]
+    pub right: String,
+    /// 两边列名相同时的关联列, eg: --on Name
+    #[arg(long, required_unless_present_all = ["left_on", "right_on"])]
+    pub on: Option<String>,
+    /// 左边文件的关联列, 默认使用--on
+    #[arg(long)]
+    pub left_on: Option<String>,
+    /// 右边文件的关联列, 默认使用--on
+    #[arg(long)]
+    pub right_on: Option<String>,
+    /// optional: [inner, left, full]
+    #[arg(long, value_parser = parse_join_type, default_value = "inner")]
+    pub how: JoinType,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 输出文件路径, 默认输出到标准输出
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    /// 输出的格式, optional: [json, yaml, toml, ndjson, csv, tsv, parquet, arrow, html, markdown]
+    #[arg(long, value_parser = parse_format, default_value = "json")]
+    pub format: OutputFormat,
+    /// csv格式输出时的分隔符
+    #[arg(long, default_value_t = ',')]
+    pub out_delimiter: char,
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    /// 旧的csv文件
+    #[arg(value_parser = verify_file)]
+    pub old: String,
+    /// 新的csv文件
+    #[arg(value_parser = verify_file)]
+    pub new: String,
+    /// 用来匹配行的主键列, 多列用逗号分隔, eg: --key Name,DOB
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub key: Vec<String>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// optional: [text, json], json outputs one patch-style record per difference
+    #[arg(long, value_parser = parse_diff_format, default_value = "text")]
+    pub format: DiffFormat,
+    /// 不输出颜色, 输出不是终端的时候也不会有颜色
+    #[arg(long, default_value_t = false)]
+    pub no_color: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvAggOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+    /// 分组的列, 多列用逗号分隔, 不指定的时候整个文件是一组, eg: --group-by Nationality
+    #[arg(long, value_delimiter = ',')]
+    pub group_by: Vec<String>,
+    /// 聚合函数, 多个用逗号分隔, eg: --agg count,avg(Kit Number)
+    /// optional: [count, count(col), sum(col), avg(col), min(col), max(col), distinct(col)]
+    #[arg(long, value_delimiter = ',', value_parser = parse_agg_spec, default_value = "count")]
+    pub agg: Vec<AggSpec>,
+    /// 这一列的每个不同的值变成一列, eg: --pivot Position, 值和--group-by的列重名时报错
+    #[arg(long)]
+    pub pivot: Option<String>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// 输出文件路径, 默认输出到标准输出
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    /// 输出的格式, optional: [json, yaml, toml, ndjson, csv, tsv, parquet, arrow, html, markdown]
+    #[arg(long, value_parser = parse_format, default_value = "json")]
     pub format: OutputFormat,
+    /// csv格式输出时的分隔符
+    #[arg(long, default_value_t = ',')]
+    pub out_delimiter: char,
+    #[command(flatten)]
+    pub writer: WriterOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDumpOpts {
+    /// 输入文件路径, '-'代表从标准输入读取
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+    /// 表名, 默认是文件名(不带后缀)
+    #[arg(long)]
+    pub table: Option<String>,
+    /// sql方言, optional: [postgres, mysql, sqlite]
+    #[arg(long, value_parser = parse_sql_dialect, default_value = "postgres")]
+    pub dialect: SqlDialect,
+    /// 每条INSERT语句插入的行数
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub batch_size: u64,
+    /// 先删除已经存在的表
+    #[arg(long, default_value_t = false)]
+    pub replace: bool,
+    /// 指定某一列的类型, 默认根据所有的值推断, eg: --type "Kit Number=string", 可以使用多次
+    #[arg(long = "type", value_name = "COLUMN=TYPE", value_parser = parse_column_type)]
+    pub types: Vec<(String, ColumnType)>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    /// sql文件路径, 默认输出到标准输出
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+    /// 直接写到sqlite数据库文件里, 而不是输出sql语句, 文件不存在的时候会创建
+    #[arg(long, conflicts_with = "output")]
+    pub sqlite: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvFromOpts {
+    /// 输入文件路径， 默认值'-'代表从标准输入读取
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+    /// 输出的csv文件路径, 默认 output.csv, '-'代表输出到标准输出
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// 输入文件的格式, optional: [json, yaml, toml, ndjson], 默认根据文件后缀判断
+    #[arg(long, value_parser = parse_input_format)]
+    pub format: Option<InputFormat>,
+    #[arg(short, long, default_value_t = ',')]
+    pub delimiter: char,
+    /// how to render arrays, optional: [json, join, index]
+    /// json: `["a","b"]`, join: `a|b`, index: columns `tags[0]`, `tags[1]`, 和 `--nested` 的表头一样
+    #[arg(long, value_parser = parse_array_format, default_value = "json")]
+    pub arrays: ArrayFormat,
+    /// the separator used by `--arrays join`
+    #[arg(long, default_value = "|")]
+    pub array_separator: String,
+    /// 行所在的数组的路径, 用点分隔, eg: --records-path data.items
+    /// 默认顶层的数组是行, toml默认是`[[rows]]`, 其他的文档是一行
+    #[arg(long)]
+    pub records_path: Option<String>,
+}
+
+/// output format of the reports printed to terminal, eg: `rcli csv stats`
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SchemaFormat {
+    Yaml,
+    JsonSchema,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    Inner,
+    Left,
+    /// full outer join
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    /// 没有列的时候是行数, 有列的时候是非空值的个数
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    /// 不同的非空值的个数
+    Distinct,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimMode {
+    None,
+    Headers,
+    Fields,
+    All,
+}
+
+/// how to handle rows whose number of fields is different from the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaggedPolicy {
+    Reject,
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum InputFormat {
+    Json,
+    Yaml,
+    Toml,
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ArrayFormat {
+    Json,
+    Join,
+    Index,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum OutputFormat {
     Json,
     Yaml,
-    // Toml,
+    Toml,
+    /// newline-delimited json, 每行一个json对象
+    Ndjson,
+    Csv,
+    Tsv,
+    /// apache parquet, 二进制的列式存储
+    Parquet,
+    /// arrow ipc file format, 也就是feather v2
+    Arrow,
+    Html,
+    /// github flavored markdown表格
+    Markdown,
 }
 impl Display for OutputFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -43,12 +681,128 @@ fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
     // 这里的范型是OutputFormat, 由于函数声明返回值指定了，这里可以省略
     format.parse()
 }
+/// parse `path` or `table=path`, the default table name is the file stem
+fn parse_sql_table(s: &str) -> Result<(String, String), anyhow::Error> {
+    let (table, path) = match s.split_once('=') {
+        Some((table, path)) if !Path::new(s).exists() => (table.to_string(), path),
+        _ => {
+            let stem = Path::new(s)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| anyhow::anyhow!("Invalid table file: {}", s))?;
+            (stem.to_string(), s)
+        }
+    };
+    let path = verify_file(path).map_err(|e| anyhow::anyhow!("{}: {}", e, path))?;
+    Ok((table, path))
+}
+
+/// parse `COLUMN[:asc|:desc]`
+fn parse_sort_key(s: &str) -> Result<SortKey, anyhow::Error> {
+    let (column, desc) = match s.rsplit_once(':') {
+        Some((column, order)) if order.eq_ignore_ascii_case("desc") => (column, true),
+        Some((column, order)) if order.eq_ignore_ascii_case("asc") => (column, false),
+        _ => (s, false),
+    };
+    Ok(SortKey {
+        column: column.to_string(),
+        desc,
+    })
+}
+
+/// parse `count` or `FUNC(COLUMN)`
+fn parse_agg_spec(s: &str) -> Result<AggSpec, anyhow::Error> {
+    let s = s.trim();
+    let (func, column) = match s.split_once('(') {
+        Some((func, rest)) => {
+            let column = rest
+                .strip_suffix(')')
+                .ok_or_else(|| anyhow::anyhow!("Invalid aggregation, expect FUNC(COLUMN)"))?;
+            (func.trim().parse()?, Some(column.to_string()))
+        }
+        None => (s.parse()?, None),
+    };
+    if column.is_none() && func != AggFunc::Count {
+        anyhow::bail!("{} needs a column, eg: {}(COLUMN)", func, func);
+    }
+    Ok(AggSpec { func, column })
+}
+
+/// parse `START..END`, both sides are optional
+fn parse_row_range(range: &str) -> Result<(usize, Option<usize>), anyhow::Error> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| anyhow::anyhow!("Invalid range, expect START..END"))?;
+    let start = if start.is_empty() { 0 } else { start.parse()? };
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(end.parse()?)
+    };
+    if end.is_some_and(|end| end < start) {
+        anyhow::bail!("Invalid range, END must not be less than START");
+    }
+    Ok((start, end))
+}
+
+fn parse_encoding(label: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Invalid encoding: {}", label))
+}
+
+fn parse_report_format(format: &str) -> Result<ReportFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_schema_format(format: &str) -> Result<SchemaFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_join_type(how: &str) -> Result<JoinType, anyhow::Error> {
+    how.parse()
+}
+
+fn parse_diff_format(format: &str) -> Result<DiffFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_trim_mode(mode: &str) -> Result<TrimMode, anyhow::Error> {
+    mode.parse()
+}
+
+fn parse_ragged_policy(policy: &str) -> Result<RaggedPolicy, anyhow::Error> {
+    policy.parse()
+}
+
+fn parse_sql_dialect(dialect: &str) -> Result<SqlDialect, anyhow::Error> {
+    dialect.parse()
+}
+
+fn parse_compression(compression: &str) -> Result<Compression, anyhow::Error> {
+    compression.parse()
+}
+
+fn parse_input_format(format: &str) -> Result<InputFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_array_format(format: &str) -> Result<ArrayFormat, anyhow::Error> {
+    format.parse()
+}
+
 impl From<OutputFormat> for &'static str {
     fn from(format: OutputFormat) -> Self {
         match format {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
-            // OutputFormat::Toml => "toml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::Html => "html",
+            OutputFormat::Markdown => "md",
         }
     }
 }
@@ -59,9 +813,447 @@ impl FromStr for OutputFormat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "json" => Ok(OutputFormat::Json),
-            "yaml" => Ok(OutputFormat::Yaml),
-            // "toml" => Ok(OutputFormat::Toml),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            "toml" => Ok(OutputFormat::Toml),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(OutputFormat::Arrow),
+            "html" => Ok(OutputFormat::Html),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            _ => Err(anyhow::anyhow!("Invalid format")),
+        }
+    }
+}
+
+impl From<ReportFormat> for &'static str {
+    fn from(format: ReportFormat) -> Self {
+        match format {
+            ReportFormat::Table => "table",
+            ReportFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(ReportFormat::Table),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid format")),
+        }
+    }
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<SchemaFormat> for &'static str {
+    fn from(format: SchemaFormat) -> Self {
+        match format {
+            SchemaFormat::Yaml => "yaml",
+            SchemaFormat::JsonSchema => "json-schema",
+        }
+    }
+}
+
+impl FromStr for SchemaFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "yaml" => Ok(SchemaFormat::Yaml),
+            "json-schema" | "jsonschema" => Ok(SchemaFormat::JsonSchema),
+            _ => Err(anyhow::anyhow!("Invalid format")),
+        }
+    }
+}
+
+impl Display for SchemaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<JoinType> for &'static str {
+    fn from(how: JoinType) -> Self {
+        match how {
+            JoinType::Inner => "inner",
+            JoinType::Left => "left",
+            JoinType::Full => "full",
+        }
+    }
+}
+
+impl FromStr for JoinType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "inner" => Ok(JoinType::Inner),
+            "left" => Ok(JoinType::Left),
+            "full" | "outer" | "full-outer" => Ok(JoinType::Full),
+            _ => Err(anyhow::anyhow!("Invalid join type: {}", s)),
+        }
+    }
+}
+
+impl Display for JoinType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<AggFunc> for &'static str {
+    fn from(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Avg => "avg",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+            AggFunc::Distinct => "distinct",
+        }
+    }
+}
+
+impl FromStr for AggFunc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "count" => Ok(AggFunc::Count),
+            "sum" => Ok(AggFunc::Sum),
+            "avg" | "mean" => Ok(AggFunc::Avg),
+            "min" => Ok(AggFunc::Min),
+            "max" => Ok(AggFunc::Max),
+            "distinct" | "count_distinct" => Ok(AggFunc::Distinct),
+            _ => Err(anyhow::anyhow!("Invalid aggregation function")),
+        }
+    }
+}
+
+impl Display for AggFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+/// the output column name, eg: `count`, `avg(Kit Number)`
+impl Display for AggSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "{}({})", self.func, column),
+            None => write!(f, "{}", self.func),
+        }
+    }
+}
+
+impl From<DiffFormat> for &'static str {
+    fn from(format: DiffFormat) -> Self {
+        match format {
+            DiffFormat::Text => "text",
+            DiffFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid format")),
+        }
+    }
+}
+
+impl Display for DiffFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<TrimMode> for &'static str {
+    fn from(mode: TrimMode) -> Self {
+        match mode {
+            TrimMode::None => "none",
+            TrimMode::Headers => "headers",
+            TrimMode::Fields => "fields",
+            TrimMode::All => "all",
+        }
+    }
+}
+
+impl FromStr for TrimMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(TrimMode::None),
+            "headers" => Ok(TrimMode::Headers),
+            "fields" => Ok(TrimMode::Fields),
+            "all" => Ok(TrimMode::All),
+            _ => Err(anyhow::anyhow!("Invalid trim mode: {}", s)),
+        }
+    }
+}
+
+impl Display for TrimMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<RaggedPolicy> for &'static str {
+    fn from(policy: RaggedPolicy) -> Self {
+        match policy {
+            RaggedPolicy::Reject => "reject",
+            RaggedPolicy::Fill => "fill",
+        }
+    }
+}
+
+impl FromStr for RaggedPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(RaggedPolicy::Reject),
+            "fill" => Ok(RaggedPolicy::Fill),
+            _ => Err(anyhow::anyhow!("Invalid ragged policy: {}", s)),
+        }
+    }
+}
+
+impl Display for RaggedPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<SqlDialect> for &'static str {
+    fn from(dialect: SqlDialect) -> Self {
+        match dialect {
+            SqlDialect::Postgres => "postgres",
+            SqlDialect::Mysql => "mysql",
+            SqlDialect::Sqlite => "sqlite",
+        }
+    }
+}
+
+impl FromStr for SqlDialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "postgres" | "postgresql" | "pg" => Ok(SqlDialect::Postgres),
+            "mysql" | "mariadb" => Ok(SqlDialect::Mysql),
+            "sqlite" => Ok(SqlDialect::Sqlite),
+            _ => Err(anyhow::anyhow!("Invalid sql dialect: {}", s)),
+        }
+    }
+}
+
+impl Display for SqlDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<Compression> for &'static str {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => "none",
+            Compression::Snappy => "snappy",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "uncompressed" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(anyhow::anyhow!("Invalid compression: {}", s)),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<InputFormat> for &'static str {
+    fn from(format: InputFormat) -> Self {
+        match format {
+            InputFormat::Json => "json",
+            InputFormat::Yaml => "yaml",
+            InputFormat::Toml => "toml",
+            InputFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(InputFormat::Json),
+            "yaml" | "yml" => Ok(InputFormat::Yaml),
+            "toml" => Ok(InputFormat::Toml),
+            "ndjson" | "jsonl" => Ok(InputFormat::Ndjson),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
 }
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<ArrayFormat> for &'static str {
+    fn from(format: ArrayFormat) -> Self {
+        match format {
+            ArrayFormat::Json => "json",
+            ArrayFormat::Join => "join",
+            ArrayFormat::Index => "index",
+        }
+    }
+}
+
+impl FromStr for ArrayFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ArrayFormat::Json),
+            "join" => Ok(ArrayFormat::Join),
+            "index" => Ok(ArrayFormat::Index),
+            _ => Err(anyhow::anyhow!("Invalid array format")),
+        }
+    }
+}
+
+impl Display for ArrayFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+/// the type of a csv column, used by type inference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum ColumnType {
+    Null,
+    Boolean,
+    Integer,
+    Float,
+    String,
+}
+
+/// parse `COLUMN=TYPE`, split at the last '=' so column name can contain '='
+fn parse_column_type(s: &str) -> Result<(String, ColumnType), anyhow::Error> {
+    let (column, ty) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Invalid column type, expect COLUMN=TYPE"))?;
+    Ok((column.to_string(), ty.parse()?))
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::Null => "null",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::String => "string",
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "null" => Ok(ColumnType::Null),
+            "bool" | "boolean" => Ok(ColumnType::Boolean),
+            "int" | "integer" => Ok(ColumnType::Integer),
+            "float" | "number" => Ok(ColumnType::Float),
+            "str" | "string" => Ok(ColumnType::String),
+            _ => Err(anyhow::anyhow!("Invalid column type: {}", s)),
+        }
+    }
+}
+
+// 让schema文件里也能用 int, bool 这些别名
+impl TryFrom<String> for ColumnType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv_opts() {
+        let opts = CsvOpts::parse_from(["csv", "-i", "assets/juventus.csv", "-d", ";"]);
+        assert!(opts.cmd.is_none());
+        assert_eq!(opts.convert.input.as_deref(), Some("assets/juventus.csv"));
+        assert_eq!(opts.convert.reader.delimiter, ';');
+        assert!(opts.convert.reader.header);
+
+        let opts = CsvOpts::parse_from(["csv", "-i", "assets/juventus.csv", "--no-header"]);
+        assert!(!opts.convert.reader.header);
+
+        let opts = CsvOpts::parse_from(["csv", "show", "-i", "assets/juventus.csv"]);
+        assert!(matches!(opts.cmd, Some(CsvSubCommand::Show(_))));
+
+        assert!(CsvOpts::try_parse_from(["csv"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_agg_spec() -> anyhow::Result<()> {
+        let spec = parse_agg_spec("avg(Kit Number)")?;
+        assert_eq!(spec.func, AggFunc::Avg);
+        assert_eq!(spec.column.as_deref(), Some("Kit Number"));
+        assert_eq!(spec.to_string(), "avg(Kit Number)");
+        assert_eq!(parse_agg_spec("count")?.to_string(), "count");
+        assert!(parse_agg_spec("sum").is_err());
+        assert!(parse_agg_spec("median(x)").is_err());
+        assert!(parse_agg_spec("max(x").is_err());
+        Ok(())
+    }
+}
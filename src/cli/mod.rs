@@ -7,10 +7,7 @@ pub mod http;
 pub mod jwt_ops;
 pub mod text;
 use self::{http::HttpSubCommand, jwt_ops::JwtSubCommand, text::TextSubCommand};
-use crate::{
-    process::{process_csv, process_genpass},
-    CmdExcuter,
-};
+use crate::{process::process_genpass, CmdExcuter};
 pub use base64_opts::Base64SubCommand;
 pub use csv_opts::CsvOpts;
 pub use genpass_opts::GenPassOpts;
@@ -22,6 +19,8 @@ pub struct Opts {
     #[command(subcommand)]
     pub cmd: SubCommand,
 }
+// 命令行参数只在启动时解析一次, 不需要为了enum的大小去Box
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 pub enum SubCommand {
     #[clap(name = "csv", about = "Show csv, or convert to other formats")]
@@ -50,21 +49,7 @@ pub enum SubCommand {
 impl CmdExcuter for SubCommand {
     async fn execute(self) -> anyhow::Result<()> {
         match self {
-            SubCommand::Csv(opts) => {
-                let output = if let Some(output) = &opts.output {
-                    output.clone()
-                } else {
-                    format!("output.{}", opts.format)
-                };
-                eprintln!("opts: {:?}", &opts);
-                process_csv(
-                    &opts.input,
-                    &output,
-                    opts.delimiter,
-                    opts.header,
-                    opts.format,
-                )?;
-            }
+            SubCommand::Csv(opts) => opts.execute().await?,
             SubCommand::GenPass(opts) => {
                 eprintln!("opts: {:?}", &opts);
                 let password = process_genpass(
@@ -0,0 +1,405 @@
+use super::{
+    build_csv_reader, compare_values, infer_value_type, new_row_writer, parse_number, typed_value,
+};
+use crate::{
+    cli::csv_opts::{AggFunc, AggSpec, CsvAggOpts},
+    utils::get_writer,
+};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+pub fn process_csv_agg(opts: &CsvAggOpts) -> Result<()> {
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    let rows = aggregate(
+        &header,
+        reader.records(),
+        &opts.group_by,
+        &opts.agg,
+        opts.pivot.as_deref(),
+    )?;
+    let mut writer = new_row_writer(
+        opts.format,
+        opts.out_delimiter,
+        &opts.writer,
+        get_writer(&opts.output)?,
+    );
+    for row in &rows {
+        writer.write_row(row)?;
+    }
+    writer.finish()?;
+    eprintln!("{} groups", rows.len());
+    Ok(())
+}
+
+/// group the records and aggregate each group, one row per group.
+/// with `pivot`, each distinct value of the pivot column becomes a column of the aggregation,
+/// the groups and the pivot columns are in the order of first appearance
+pub fn aggregate(
+    header: &StringRecord,
+    records: impl Iterator<Item = csv::Result<StringRecord>>,
+    group_by: &[String],
+    aggs: &[AggSpec],
+    pivot: Option<&str>,
+) -> Result<Vec<Value>> {
+    let position = |name: &str| {
+        header
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow::anyhow!("column not found: {}", name))
+    };
+    let group_indexes = group_by
+        .iter()
+        .map(|column| position(column))
+        .collect::<Result<Vec<_>>>()?;
+    let agg_indexes = aggs
+        .iter()
+        .map(|agg| agg.column.as_deref().map(position).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    let pivot_index = pivot.map(position).transpose()?;
+
+    let new_cell = || aggs.iter().map(|agg| Accumulator::new(agg.func)).collect();
+    let mut groups: Vec<Group> = vec![];
+    let mut group_positions = HashMap::new();
+    let mut pivot_values: Vec<String> = vec![];
+    let mut pivot_positions = HashMap::new();
+    // 没有分组的时候整个文件是一组, 即使没有数据也输出一行
+    if group_by.is_empty() {
+        group_positions.insert(vec![], 0);
+        groups.push(Group::default());
+    }
+    for record in records {
+        let record = record?;
+        let key = group_indexes
+            .iter()
+            .map(|&idx| record.get(idx).unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        let group = *group_positions.entry(key.clone()).or_insert_with(|| {
+            groups.push(Group { key, cells: vec![] });
+            groups.len() - 1
+        });
+        let cell = match pivot_index {
+            Some(idx) => {
+                let value = record.get(idx).unwrap_or_default();
+                *pivot_positions.entry(value.to_string()).or_insert_with(|| {
+                    pivot_values.push(value.to_string());
+                    pivot_values.len() - 1
+                })
+            }
+            None => 0,
+        };
+        let cells = &mut groups[group].cells;
+        if cells.len() <= cell {
+            cells.resize_with(cell + 1, || None);
+        }
+        let accumulators = cells[cell].get_or_insert_with(new_cell);
+        for ((accumulator, idx), agg) in accumulators.iter_mut().zip(&agg_indexes).zip(aggs) {
+            let value = idx.map(|idx| record.get(idx).unwrap_or_default());
+            accumulator
+                .add(value)
+                .with_context(|| format!("can't aggregate {}", agg))?;
+        }
+    }
+
+    // 只有一个聚合函数的时候列名就是透视列的值
+    let pivot_column = |value: &String, agg: &AggSpec| {
+        if aggs.len() == 1 {
+            value.clone()
+        } else {
+            format!("{}_{}", value, agg)
+        }
+    };
+    for value in &pivot_values {
+        for agg in aggs {
+            let name = pivot_column(value, agg);
+            if group_by.contains(&name) {
+                anyhow::bail!(
+                    "pivot column {} conflicts with the group-by column of the same name",
+                    name
+                );
+            }
+        }
+    }
+
+    let empty: Vec<Accumulator> = new_cell();
+    let rows = groups
+        .iter()
+        .map(|group| {
+            let mut row = Map::new();
+            for (name, value) in group_by.iter().zip(&group.key) {
+                row.insert(name.clone(), Value::String(value.clone()));
+            }
+            let cell = |idx: usize| {
+                group
+                    .cells
+                    .get(idx)
+                    .and_then(Option::as_ref)
+                    .unwrap_or(&empty)
+            };
+            match pivot {
+                None => {
+                    for (agg, accumulator) in aggs.iter().zip(cell(0)) {
+                        row.insert(agg.to_string(), accumulator.finish());
+                    }
+                }
+                Some(_) => {
+                    for (idx, value) in pivot_values.iter().enumerate() {
+                        for (agg, accumulator) in aggs.iter().zip(cell(idx)) {
+                            row.insert(pivot_column(value, agg), accumulator.finish());
+                        }
+                    }
+                }
+            }
+            Value::Object(row)
+        })
+        .collect();
+    Ok(rows)
+}
+
+#[derive(Default)]
+struct Group {
+    key: Vec<String>,
+    /// 每个透视列的值一组聚合, 没有透视的时候只有一组
+    cells: Vec<Option<Vec<Accumulator>>>,
+}
+
+enum Accumulator {
+    Count(u64),
+    Sum {
+        /// 全是整数并且没有溢出的时候是整数的和
+        int: Option<i64>,
+        float: f64,
+        count: u64,
+    },
+    Avg {
+        sum: f64,
+        count: u64,
+    },
+    Min(Option<String>),
+    Max(Option<String>),
+    Distinct(HashSet<String>),
+}
+
+impl Accumulator {
+    fn new(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => Accumulator::Count(0),
+            AggFunc::Sum => Accumulator::Sum {
+                int: Some(0),
+                float: 0.0,
+                count: 0,
+            },
+            AggFunc::Avg => Accumulator::Avg { sum: 0.0, count: 0 },
+            AggFunc::Min => Accumulator::Min(None),
+            AggFunc::Max => Accumulator::Max(None),
+            AggFunc::Distinct => Accumulator::Distinct(HashSet::new()),
+        }
+    }
+
+    /// `None` is a row for `count` without column, empty values are ignored
+    fn add(&mut self, value: Option<&str>) -> Result<()> {
+        let Some(value) = value else {
+            if let Accumulator::Count(count) = self {
+                *count += 1;
+            }
+            return Ok(());
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(());
+        }
+        let number =
+            || parse_number(value).ok_or_else(|| anyhow::anyhow!("not a number: {}", value));
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum { int, float, count } => {
+                *float += number()?;
+                *int = match value.parse::<i64>() {
+                    Ok(v) => int.and_then(|int| int.checked_add(v)),
+                    Err(_) => None,
+                };
+                *count += 1;
+            }
+            Accumulator::Avg { sum, count } => {
+                *sum += number()?;
+                *count += 1;
+            }
+            Accumulator::Min(min) => {
+                if min
+                    .as_deref()
+                    .is_none_or(|min| compare_values(value, min) == Ordering::Less)
+                {
+                    *min = Some(value.to_string());
+                }
+            }
+            Accumulator::Max(max) => {
+                if max
+                    .as_deref()
+                    .is_none_or(|max| compare_values(value, max) == Ordering::Greater)
+                {
+                    *max = Some(value.to_string());
+                }
+            }
+            Accumulator::Distinct(values) => {
+                values.insert(value.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::from(*count),
+            Accumulator::Sum { count: 0, .. } | Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Sum { int: Some(int), .. } => Value::from(*int),
+            Accumulator::Sum { float, .. } => Value::from(*float),
+            Accumulator::Avg { sum, count } => Value::from(sum / *count as f64),
+            Accumulator::Min(value) | Accumulator::Max(value) => value
+                .as_deref()
+                .map_or(Value::Null, |v| typed_value(v, infer_value_type(v))),
+            Accumulator::Distinct(values) => Value::from(values.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spec(func: AggFunc, column: Option<&str>) -> AggSpec {
+        AggSpec {
+            func,
+            column: column.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_aggregate() -> Result<()> {
+        let data = "team,pos,kit\na,gk,1\na,df,4\nb,gk,12\na,df,\nb,fw,9\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let header = reader.headers()?.clone();
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let aggs = [
+            spec(AggFunc::Count, None),
+            spec(AggFunc::Avg, Some("kit")),
+            spec(AggFunc::Max, Some("kit")),
+            spec(AggFunc::Distinct, Some("pos")),
+        ];
+        let rows = aggregate(
+            &header,
+            records.iter().cloned().map(Ok),
+            &["team".to_string()],
+            &aggs,
+            None,
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"team": "a", "count": 3, "avg(kit)": 2.5, "max(kit)": 4, "distinct(pos)": 2}),
+                json!({"team": "b", "count": 2, "avg(kit)": 10.5, "max(kit)": 12, "distinct(pos)": 2}),
+            ]
+        );
+
+        let rows = aggregate(
+            &header,
+            records.iter().cloned().map(Ok),
+            &["team".to_string()],
+            &[spec(AggFunc::Sum, Some("kit"))],
+            Some("pos"),
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"team": "a", "gk": 1, "df": 4, "fw": null}),
+                json!({"team": "b", "gk": 12, "df": null, "fw": 9}),
+            ]
+        );
+
+        // 没有分组的时候整个文件一行
+        let rows = aggregate(
+            &header,
+            std::iter::empty(),
+            &[],
+            &[spec(AggFunc::Count, None)],
+            None,
+        )?;
+        assert_eq!(rows, vec![json!({"count": 0})]);
+
+        // 透视列的值和分组的列重名
+        let data = "team,opponent\na,b\nb,team\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let conflict = reader.headers()?.clone();
+        let err = aggregate(
+            &conflict,
+            reader.records(),
+            &["team".to_string()],
+            &[spec(AggFunc::Count, None)],
+            Some("opponent"),
+        );
+        assert!(err.unwrap_err().to_string().contains("pivot column team"));
+
+        // min/max: 数字比字符串小, NaN是字符串
+        let data = "kit\n10\nNaN\n9\nx\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let mixed = reader.headers()?.clone();
+        let rows = aggregate(
+            &mixed,
+            reader.records(),
+            &[],
+            &[
+                spec(AggFunc::Min, Some("kit")),
+                spec(AggFunc::Max, Some("kit")),
+            ],
+            None,
+        )?;
+        assert_eq!(rows, vec![json!({"min(kit)": 9, "max(kit)": "x"})]);
+
+        let err = aggregate(
+            &header,
+            records.iter().cloned().map(Ok),
+            &[],
+            &[spec(AggFunc::Sum, Some("pos"))],
+            None,
+        );
+        assert!(err.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_agg_parquet_types_of_all_rows() -> Result<()> {
+        let input = std::env::temp_dir().join("rcli_test_agg_types.csv");
+        std::fs::write(&input, "team,kit\na,4\nb,x\n")?;
+        let output = std::env::temp_dir().join("rcli_test_agg_types.parquet");
+        let opts = <CsvAggOpts as clap::Parser>::parse_from([
+            "agg",
+            "-i",
+            input.to_str().unwrap(),
+            "--group-by",
+            "team",
+            "--agg",
+            "max(kit)",
+            "--format",
+            "parquet",
+            "--row-group-size",
+            "1",
+            "-o",
+            output.to_str().unwrap(),
+        ]);
+        process_csv_agg(&opts)?;
+        let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            std::fs::File::open(output)?,
+        )?;
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(
+            builder.schema().field(1).data_type(),
+            &arrow_schema::DataType::Utf8
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+use super::{collect_headers, value_to_cell, RowWriter};
+use crate::cli::csv_opts::{ColumnType, Compression, OutputFormat, WriterOpts};
+use anyhow::Result;
+use arrow_array::{
+    builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::writer::{FileWriter, IpcWriteOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{arrow::ArrowWriter, basic, file::properties::WriterProperties};
+use serde_json::Value;
+use std::{collections::HashSet, io::Write, mem, sync::Arc};
+
+/// write the rows as parquet or arrow ipc, the rows are buffered and written one row group at a time.
+/// the schema is given by `with_schema`, or inferred from all the rows, which are buffered until
+/// `finish`: integer, float, boolean, or string for everything else (nested values are json),
+/// columns that are all null are strings
+pub struct ColumnarWriter {
+    format: OutputFormat,
+    row_group_size: u64,
+    compression: Option<Compression>,
+    /// arrow的writer创建之后就交给它了
+    output: Option<Box<dyn Write>>,
+    rows: Vec<Value>,
+    schema: Option<SchemaRef>,
+    sink: Option<Sink>,
+}
+
+enum Sink {
+    // ArrowWriter要求Send, 所以先写到Vec里, 每个row group写完之后再转到输出
+    Parquet {
+        writer: ArrowWriter<Vec<u8>>,
+        schema: SchemaRef,
+    },
+    Arrow {
+        writer: FileWriter<Box<dyn Write>>,
+        schema: SchemaRef,
+    },
+}
+
+impl ColumnarWriter {
+    pub fn new(format: OutputFormat, opts: &WriterOpts, output: Box<dyn Write>) -> Self {
+        Self {
+            format,
+            row_group_size: opts.row_group_size,
+            compression: opts.compression,
+            output: Some(output),
+            rows: vec![],
+            schema: None,
+            sink: None,
+        }
+    }
+
+    /// use the schema instead of inferring it, the writer is created right away so the
+    /// invalid options are reported before any row is written
+    pub fn with_schema(mut self, schema: Schema) -> Result<Self> {
+        self.schema = Some(Arc::new(schema));
+        self.open()?;
+        Ok(self)
+    }
+
+    /// create the writer, the schema is inferred from the buffered rows if not given
+    fn open(&mut self) -> Result<()> {
+        let schema = match &self.schema {
+            Some(schema) => schema.clone(),
+            None => Arc::new(infer_schema(&self.rows)),
+        };
+        let sink = match self.format {
+            OutputFormat::Parquet => {
+                let compression = match self.compression.unwrap_or(Compression::Snappy) {
+                    Compression::None => basic::Compression::UNCOMPRESSED,
+                    Compression::Snappy => basic::Compression::SNAPPY,
+                    Compression::Gzip => basic::Compression::GZIP(Default::default()),
+                    Compression::Zstd => basic::Compression::ZSTD(Default::default()),
+                    Compression::Lz4 => basic::Compression::LZ4_RAW,
+                };
+                let props = WriterProperties::builder()
+                    .set_compression(compression)
+                    .set_max_row_group_row_count(Some(self.row_group_size as usize))
+                    .build();
+                let writer = ArrowWriter::try_new(vec![], schema.clone(), Some(props))?;
+                Sink::Parquet { writer, schema }
+            }
+            _ => {
+                let compression = match self.compression {
+                    None | Some(Compression::None) => None,
+                    Some(Compression::Lz4) => Some(arrow_ipc::CompressionType::LZ4_FRAME),
+                    Some(Compression::Zstd) => Some(arrow_ipc::CompressionType::ZSTD),
+                    Some(compression) => {
+                        anyhow::bail!("arrow doesn't support {} compression", compression)
+                    }
+                };
+                let options = IpcWriteOptions::default().try_with_compression(compression)?;
+                let output = self.output.take().expect("arrow output is taken once");
+                let writer = FileWriter::try_new_with_options(output, &schema, options)?;
+                Sink::Arrow { writer, schema }
+            }
+        };
+        self.sink = Some(sink);
+        Ok(())
+    }
+
+    /// write the buffered rows, one row group for each `row_group_size` rows
+    fn flush_rows(&mut self) -> Result<()> {
+        if self.sink.is_none() {
+            self.open()?;
+        }
+        let rows = mem::take(&mut self.rows);
+        if let Some(sink) = self.sink.as_mut() {
+            for rows in rows.chunks(self.row_group_size as usize) {
+                write_rows(sink, rows, &mut self.output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RowWriter for ColumnarWriter {
+    fn write_row(&mut self, row: &Value) -> Result<()> {
+        self.rows.push(row.clone());
+        // 没有schema的时候要看过所有的行才知道每列的类型, 一直缓存到finish
+        if self.schema.is_some() && self.rows.len() as u64 >= self.row_group_size {
+            self.flush_rows()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // 没有数据的时候也要写出一个空的文件
+        if !self.rows.is_empty() || self.sink.is_none() {
+            self.flush_rows()?;
+        }
+        match self.sink.as_mut() {
+            Some(Sink::Parquet { writer, .. }) => {
+                writer.finish()?;
+                drain(writer, &mut self.output)?;
+            }
+            Some(Sink::Arrow { writer, .. }) => {
+                writer.finish()?;
+                writer.get_mut().flush()?;
+            }
+            None => {}
+        }
+        if let Some(output) = self.output.as_mut() {
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_rows(sink: &mut Sink, rows: &[Value], output: &mut Option<Box<dyn Write>>) -> Result<()> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    match sink {
+        Sink::Parquet { writer, schema } => {
+            writer.write(&record_batch(schema, rows)?)?;
+            writer.flush()?;
+            drain(writer, output)
+        }
+        Sink::Arrow { writer, schema } => Ok(writer.write(&record_batch(schema, rows)?)?),
+    }
+}
+
+/// move the written parquet bytes to the output
+fn drain(writer: &mut ArrowWriter<Vec<u8>>, output: &mut Option<Box<dyn Write>>) -> Result<()> {
+    let data = mem::take(writer.inner_mut());
+    if let Some(output) = output {
+        output.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// the non-null value of a column, rows are objects or arrays (`--as-arrays`)
+pub fn row_cell<'a>(row: &'a Value, idx: usize, name: &str) -> Option<&'a Value> {
+    match row {
+        Value::Array(values) => values.get(idx),
+        _ => row.get(name),
+    }
+    .filter(|value| !value.is_null())
+}
+
+/// the column names of the rows, arrays don't have keys so they are `col1, col2, ...`
+pub fn row_columns(rows: &[Value]) -> Vec<String> {
+    match rows.first() {
+        Some(Value::Array(_)) => {
+            let width = rows.iter().filter_map(Value::as_array).map(Vec::len).max();
+            (1..=width.unwrap_or_default())
+                .map(|idx| format!("col{}", idx))
+                .collect()
+        }
+        _ => collect_headers(rows),
+    }
+}
+
+/// the schema of the typed output columns, the columns without a type are strings
+pub fn columnar_schema(columns: &[(String, Option<ColumnType>)]) -> Schema {
+    let fields = columns
+        .iter()
+        .map(|(name, ty)| {
+            let data_type = match ty {
+                Some(ColumnType::Integer) => DataType::Int64,
+                Some(ColumnType::Float) => DataType::Float64,
+                Some(ColumnType::Boolean) => DataType::Boolean,
+                _ => DataType::Utf8,
+            };
+            Field::new(name, data_type, true)
+        })
+        .collect::<Vec<_>>();
+    Schema::new(fields)
+}
+
+fn infer_schema(rows: &[Value]) -> Schema {
+    let fields = row_columns(rows)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let mut data_type = DataType::Null;
+            for value in rows.iter().filter_map(|row| row_cell(row, idx, &name)) {
+                let ty = match value {
+                    Value::Bool(_) => DataType::Boolean,
+                    Value::Number(n) if n.is_i64() => DataType::Int64,
+                    Value::Number(_) => DataType::Float64,
+                    _ => DataType::Utf8,
+                };
+                data_type = match (data_type, ty) {
+                    (DataType::Null, ty) => ty,
+                    (a, b) if a == b => a,
+                    (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => {
+                        DataType::Float64
+                    }
+                    _ => DataType::Utf8,
+                };
+            }
+            if data_type == DataType::Null {
+                data_type = DataType::Utf8;
+            }
+            Field::new(name, data_type, true)
+        })
+        .collect::<Vec<_>>();
+    Schema::new(fields)
+}
+
+fn record_batch(schema: &SchemaRef, rows: &[Value]) -> Result<RecordBatch> {
+    let names = schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect::<HashSet<_>>();
+    for row in rows.iter().filter_map(Value::as_object) {
+        if let Some(key) = row.keys().find(|key| !names.contains(key.as_str())) {
+            anyhow::bail!("column {} is not in the schema", key);
+        }
+    }
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| column_array(field, idx, rows))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn column_array(field: &Field, idx: usize, rows: &[Value]) -> Result<ArrayRef> {
+    let name = field.name();
+    let values = rows.iter().map(|row| row_cell(row, idx, name));
+    // 列的类型由所有的值推断或者由schema给定, 给定的schema和值不一样的时候只能报错
+    let mismatch = |value: &Value| {
+        anyhow::anyhow!(
+            "can't write {} to the {} column {}, use --type to set the column type",
+            value,
+            field.data_type(),
+            name
+        )
+    };
+    let array: ArrayRef = match field.data_type() {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(rows.len());
+            for value in values {
+                let value = value.map(|v| v.as_i64().ok_or_else(|| mismatch(v)));
+                builder.append_option(value.transpose()?);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(rows.len());
+            for value in values {
+                let value = value.map(|v| v.as_f64().ok_or_else(|| mismatch(v)));
+                builder.append_option(value.transpose()?);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(rows.len());
+            for value in values {
+                let value = value.map(|v| v.as_bool().ok_or_else(|| mismatch(v)));
+                builder.append_option(value.transpose()?);
+            }
+            Arc::new(builder.finish())
+        }
+        _ => {
+            let mut builder = StringBuilder::new();
+            for value in values {
+                builder.append_option(value.map(value_to_cell));
+            }
+            Arc::new(builder.finish())
+        }
+    };
+    Ok(array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{cast::AsArray, types::Int64Type};
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+    use std::{fs, path::PathBuf};
+
+    fn rows() -> Vec<Value> {
+        vec![
+            json!({"name": "a", "kit": 1, "height": 1.8, "starter": true, "note": null}),
+            json!({"name": "b", "kit": 2, "height": 2, "starter": null, "note": null}),
+            json!({"name": "c", "kit": null, "height": 1.75, "starter": false, "note": null}),
+        ]
+    }
+
+    fn write(format: OutputFormat, opts: &WriterOpts, rows: &[Value]) -> Result<PathBuf> {
+        let path = std::env::temp_dir().join(format!("rcli_test_columnar.{}", format));
+        let mut writer = ColumnarWriter::new(format, opts, Box::new(fs::File::create(&path)?));
+        for row in rows {
+            writer.write_row(row)?;
+        }
+        writer.finish()?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_infer_schema() {
+        let schema = infer_schema(&rows());
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ("name", DataType::Utf8),
+                ("kit", DataType::Int64),
+                ("height", DataType::Float64),
+                ("starter", DataType::Boolean),
+                ("note", DataType::Utf8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parquet_writer() -> Result<()> {
+        let opts = WriterOpts {
+            row_group_size: 2,
+            compression: Some(Compression::Zstd),
+            ..Default::default()
+        };
+        let path = write(OutputFormat::Parquet, &opts, &rows())?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path)?)?;
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        let batches = builder.build()?.collect::<Result<Vec<_>, _>>()?;
+        let kits = batches
+            .iter()
+            .flat_map(|batch| batch.column(1).as_primitive::<Int64Type>().iter())
+            .collect::<Vec<_>>();
+        assert_eq!(kits, vec![Some(1), Some(2), None]);
+
+        // 类型由所有的行推断, 后面的组里的字符串让整列变成字符串
+        let mut rows = rows();
+        rows.push(json!({"name": "d", "kit": "x"}));
+        let path = write(OutputFormat::Parquet, &opts, &rows)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path)?)?;
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.schema().field(1).data_type(), &DataType::Utf8);
+
+        // 给定的schema和值不一样的时候报错
+        let path = std::env::temp_dir().join("rcli_test_columnar_schema.parquet");
+        let schema = columnar_schema(&[("kit".to_string(), Some(ColumnType::Integer))]);
+        let mut writer = ColumnarWriter::new(
+            OutputFormat::Parquet,
+            &opts,
+            Box::new(fs::File::create(&path)?),
+        )
+        .with_schema(schema)?;
+        writer.write_row(&json!({"kit": 1}))?;
+        let ret = writer
+            .write_row(&json!({"kit": "x"}))
+            .and_then(|_| writer.finish());
+        assert!(ret.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrow_writer() -> Result<()> {
+        let path = write(OutputFormat::Arrow, &WriterOpts::default(), &rows())?;
+        let reader = FileReader::try_new(fs::File::open(path)?, None)?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        assert_eq!(batches[0].schema().field(2).data_type(), &DataType::Float64);
+
+        let opts = WriterOpts {
+            compression: Some(Compression::Snappy),
+            ..Default::default()
+        };
+        assert!(write(OutputFormat::Arrow, &opts, &rows()).is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,18 @@
-use crate::cli::csv_opts::OutputFormat;
-use csv::ReaderBuilder;
+use super::{
+    apply_type_overrides, build_csv_reader, columnar_schema, is_spreadsheet, new_row_writer,
+    parse_header_path, process_csv_parallel, read_sheet, resolve_sort_keys, select_columns,
+    sort_records, update_column_types, values_to_nested_row, ColumnProtector, ColumnarWriter,
+    CsvMapping, EncodingWriter, Expr, Mapper, Output, PathSegment, RowWriter,
+};
+use crate::{
+    cli::csv_opts::{ColumnType, CsvConvertOpts, OutputFormat},
+    utils::get_writer,
+};
+use csv::StringRecord;
+use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::fs;
+use std::time::Instant;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
@@ -14,38 +24,361 @@ pub struct Row {
     age: u8,
 }
 
-pub fn process_csv(
-    input: &str,
-    output: &str,
-    delimiter: char,
-    header: bool,
-    output_format: OutputFormat,
-) -> anyhow::Result<()> {
-    // let mut reader = Reader::from_path(input)?;
-    let mut reader = ReaderBuilder::new()
-        .delimiter(delimiter as u8)
-        .has_headers(header)
-        .from_path(input)?;
-
-    let mut ret = Vec::with_capacity(128);
-    // 不能两个mutable borrow
-    let header = reader.headers()?.clone();
-    for result in reader.records() {
-        let record = result?;
-        let iter = header.iter().zip(record.iter());
-        let row = match output_format {
-            OutputFormat::Json => iter.collect::<Value>(),
-            OutputFormat::Yaml => iter.collect::<Value>(),
-        };
-        // let row = .collect::<Value>();
-        ret.push(row);
+pub fn process_csv(opts: &CsvConvertOpts, output: &str) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let input = opts
+        .input
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("missing csv input"))?;
+    let binary = matches!(opts.format, OutputFormat::Parquet | OutputFormat::Arrow);
+    if binary && opts.out_encoding.is_some() {
+        anyhow::bail!("--out-encoding doesn't apply to {} output", opts.format);
     }
-    let content = match output_format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
-    };
+    let spreadsheet = is_spreadsheet(input);
+    if !spreadsheet && (opts.sheet.is_some() || opts.range.is_some()) {
+        anyhow::bail!("--sheet and --range are only for xlsx, xls and ods input");
+    }
+    if opts.parallel {
+        if spreadsheet {
+            anyhow::bail!("--parallel doesn't support xlsx, xls and ods input");
+        }
+        let count = process_csv_parallel(opts, input, output)?;
+        print_stats(count, start);
+        return Ok(());
+    }
+    let mut reader = None;
+    let header;
+    let mut types;
+    let mut buffered = None;
+    if spreadsheet {
+        // 表格的单元格有类型, 不需要推断
+        let sheet = read_sheet(
+            input,
+            opts.sheet.as_deref(),
+            opts.range.as_deref(),
+            &opts.reader,
+        )?;
+        eprintln!("sheet: {}", sheet.name);
+        header = sheet.header;
+        types = sheet.types;
+        buffered = Some(sheet.records);
+    } else {
+        let mut csv = build_csv_reader(input, &opts.reader)?;
+        // 不能两个mutable borrow
+        header = csv.headers()?;
+        types = vec![ColumnType::String; header.len()];
+        // parquet和arrow的列有类型, 总是根据整个文件推断
+        let infer = opts.infer || binary;
+        if infer && input == "-" {
+            // 标准输入只能读一次, 只能先读进内存再推断类型
+            types = vec![ColumnType::Null; header.len()];
+            let records = csv.records().collect::<Result<Vec<_>, _>>()?;
+            for record in &records {
+                update_column_types(&mut types, record);
+            }
+            buffered = Some(records);
+        } else if infer {
+            // 推断类型需要先扫描一遍文件, 第二遍再转换, 这样不需要把整个文件读进内存
+            types = vec![ColumnType::Null; header.len()];
+            let mut reader = build_csv_reader(input, &opts.reader)?;
+            let mut record = StringRecord::new();
+            while reader.read_record(&mut record)? {
+                update_column_types(&mut types, &record);
+            }
+        }
+        reader = Some(csv);
+    }
+    let converter = RowConverter::new(opts, &header, types)?;
+    let sort_keys = resolve_sort_keys(&header, &opts.sort_by)?;
 
-    fs::write(output, content)?;
+    let mut writer: Box<dyn RowWriter> = if binary {
+        Box::new(columnar_writer(opts, &converter, output)?)
+    } else {
+        new_row_writer(
+            opts.format,
+            opts.out_delimiter,
+            &opts.writer,
+            open_output(output, opts.out_encoding)?,
+        )
+    };
+    let mut count = 0u64;
+    let mut write = |record: &StringRecord| -> anyhow::Result<()> {
+        writer.write_row(&converter.convert(record)?)?;
+        count += 1;
+        Ok(())
+    };
 
+    // 排序需要先把所有行读进内存, 不排序的时候逐行写出
+    let mut sorted = vec![];
+    let mut process = |record: &StringRecord| -> anyhow::Result<()> {
+        if !converter.matches(record) {
+            return Ok(());
+        }
+        if sort_keys.is_empty() {
+            write(record)?;
+        } else {
+            sorted.push(record.clone());
+        }
+        Ok(())
+    };
+    if let Some(records) = &buffered {
+        for record in records {
+            process(record)?;
+        }
+    } else if let Some(reader) = &mut reader {
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record)? {
+            process(&record)?;
+        }
+    }
+    sort_records(&mut sorted, &sort_keys);
+    for record in &sorted {
+        write(record)?;
+    }
+    writer.finish()?;
+    print_stats(count, start);
     Ok(())
 }
+
+/// filter, map and type the records into output rows, shared by the sequential and parallel conversion
+pub struct RowConverter {
+    /// 整个文件推断出的类型, 不包括 `--type`
+    inferred: Vec<ColumnType>,
+    filter: Option<Expr>,
+    mapper: Mapper,
+    protector: Option<ColumnProtector>,
+    paths: Vec<Vec<PathSegment>>,
+    as_arrays: bool,
+    nested: bool,
+}
+
+impl RowConverter {
+    /// `types` are the types of all columns before `--type` overrides
+    pub fn new(
+        opts: &CsvConvertOpts,
+        header: &StringRecord,
+        mut types: Vec<ColumnType>,
+    ) -> anyhow::Result<Self> {
+        let inferred = types.clone();
+        apply_type_overrides(header, &mut types, &opts.types)?;
+        let filter = opts
+            .filter
+            .as_deref()
+            .map(|filter| Expr::parse(filter, header))
+            .transpose()?;
+        // 有映射文件的时候按映射输出, 否则是 --select 的列
+        let mapper = match &opts.mapping {
+            Some(path) => Mapper::new(&CsvMapping::load(path)?, header, &types)?,
+            None => Mapper::select(header, &select_columns(header, &opts.select)?, &types),
+        };
+        let protector = ColumnProtector::new(opts, mapper.header())?;
+        let paths = mapper
+            .header()
+            .iter()
+            .map(parse_header_path)
+            .collect::<Vec<_>>();
+        Ok(Self {
+            inferred,
+            filter,
+            mapper,
+            protector,
+            paths,
+            as_arrays: opts.as_arrays,
+            nested: opts.nested,
+        })
+    }
+
+    /// the output columns and their types for parquet and arrow, the types must come from the
+    /// whole file. arrays are `col1, col2, ...`, nested objects and arrays are json strings
+    pub fn output_columns(&self) -> anyhow::Result<Vec<(String, Option<ColumnType>)>> {
+        let mut types = self.mapper.column_types(&self.inferred)?;
+        if let Some(protector) = &self.protector {
+            protector.update_types(&mut types);
+        }
+        if self.as_arrays {
+            let names = (1..=types.len()).map(|idx| format!("col{}", idx));
+            return Ok(names.zip(types).collect());
+        }
+        if !self.nested {
+            let names = self.mapper.header().iter().map(str::to_string);
+            return Ok(names.zip(types).collect());
+        }
+        let mut columns: Vec<(String, Option<ColumnType>)> = vec![];
+        for (path, ty) in self.paths.iter().zip(types) {
+            let Some(PathSegment::Key(key)) = path.first() else {
+                continue;
+            };
+            let ty = if path.len() == 1 { ty } else { None };
+            match columns.iter_mut().find(|(name, _)| name == key) {
+                Some(column) => column.1 = None,
+                None => columns.push((key.clone(), ty)),
+            }
+        }
+        Ok(columns)
+    }
+
+    /// whether the record passes `--where`
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(record))
+    }
+
+    pub fn convert(&self, record: &StringRecord) -> anyhow::Result<Value> {
+        let mut values = self.mapper.map(record)?;
+        if let Some(protector) = &self.protector {
+            protector.apply(&mut values)?;
+        }
+        let row = if self.as_arrays {
+            Value::Array(values)
+        } else if self.nested {
+            values_to_nested_row(&self.paths, values)?
+        } else {
+            self.mapper.header().iter().zip(values).collect()
+        };
+        Ok(row)
+    }
+}
+
+/// the parquet or arrow writer with the schema of the converter, the schema is checked
+/// before the output is created
+pub fn columnar_writer(
+    opts: &CsvConvertOpts,
+    converter: &RowConverter,
+    output: &str,
+) -> anyhow::Result<ColumnarWriter> {
+    let schema = columnar_schema(&converter.output_columns()?);
+    ColumnarWriter::new(opts.format, &opts.writer, get_writer(output)?).with_schema(schema)
+}
+
+/// open the output, transcoded by `--out-encoding` if given
+pub fn open_output(output: &str, encoding: Option<&'static Encoding>) -> anyhow::Result<Output> {
+    let output = get_writer(output)?;
+    Ok(match encoding {
+        Some(encoding) => Output::Encoded(EncodingWriter::new(output, encoding)?),
+        None => Output::Plain(output),
+    })
+}
+
+fn print_stats(count: u64, start: Instant) {
+    let elapsed = start.elapsed();
+    eprintln!(
+        "converted {} rows in {:.2?} ({:.0} rows/s)",
+        count,
+        elapsed,
+        count as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_process_csv_infer() -> anyhow::Result<()> {
+        let output = std::env::temp_dir().join("rcli_test_infer.json");
+        let output = output.to_str().unwrap();
+        let opts = CsvConvertOpts::parse_from(["csv", "-i", "assets/juventus.csv", "--infer"]);
+        process_csv(&opts, output)?;
+        let ret: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(output)?)?;
+        assert_eq!(ret[0]["Kit Number"], 1);
+        assert_eq!(ret[0]["DOB"], "Apr 18, 1990 (29)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_filter() -> anyhow::Result<()> {
+        let output = std::env::temp_dir().join("rcli_test_filter.json");
+        let output = output.to_str().unwrap();
+        let opts = CsvConvertOpts::parse_from([
+            "csv",
+            "-i",
+            "assets/juventus.csv",
+            "--where",
+            "Position == 'Goalkeeper' && Kit Number > 10",
+            "--select",
+            "Name,Kit Number",
+            "--sort-by",
+            "Kit Number:desc",
+        ]);
+        process_csv(&opts, output)?;
+        let ret: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(output)?)?;
+        assert_eq!(ret.len(), 3);
+        assert_eq!(
+            ret[0],
+            serde_json::json!({"Name": "Gianluigi Buffon", "Kit Number": "77"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_parquet_schema() -> anyhow::Result<()> {
+        use arrow_schema::DataType;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = std::env::temp_dir();
+        let input = dir.join("rcli_test_schema.csv");
+        // 第一组都是整数, 后面才有小数
+        std::fs::write(&input, "id,score,name\n1,1,a\n2,2,b\n3,2.5,c\n")?;
+        let input = input.to_str().unwrap();
+        let output = dir.join("rcli_test_schema.parquet");
+        let output = output.to_str().unwrap();
+        let args = [
+            "csv",
+            "-i",
+            input,
+            "--format",
+            "parquet",
+            "--row-group-size",
+            "1",
+        ];
+        process_csv(&CsvConvertOpts::parse_from(args), output)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(output)?)?;
+        let types = reader
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![DataType::Int64, DataType::Float64, DataType::Utf8]
+        );
+        let rows = reader.build()?.map(|batch| batch.map(|b| b.num_rows()));
+        assert_eq!(rows.sum::<Result<usize, _>>()?, 3);
+
+        // 声明的类型不符合所有的值, 写之前就报错
+        std::fs::remove_file(output)?;
+        let args = args.iter().chain(&["--type", "score=integer"]);
+        assert!(process_csv(&CsvConvertOpts::parse_from(args), output).is_err());
+        assert!(!std::path::Path::new(output).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_mapping() -> anyhow::Result<()> {
+        let output = std::env::temp_dir().join("rcli_test_mapping.json");
+        let output = output.to_str().unwrap();
+        let opts = CsvConvertOpts::parse_from([
+            "csv",
+            "-i",
+            "assets/juventus.csv",
+            "--mapping",
+            "assets/juventus_mapping.yaml",
+        ]);
+        process_csv(&opts, output)?;
+        let ret: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(output)?)?;
+        assert_eq!(
+            ret[0],
+            serde_json::json!({
+                "name": "Wojciech Szczesny",
+                "kit_number": 1,
+                "birthday": "1990-04-18",
+                "country": "Poland",
+                "label": "Wojciech Szczesny #1",
+            })
+        );
+        Ok(())
+    }
+}
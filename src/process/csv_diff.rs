@@ -0,0 +1,283 @@
+use super::build_csv_reader;
+use crate::cli::csv_opts::{CsvDiffOpts, DiffFormat};
+use anyhow::Result;
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{stdout, IsTerminal},
+};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// one difference between the two snapshots, serialized like a json patch operation
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DiffRecord {
+    AddColumn {
+        column: String,
+    },
+    RemoveColumn {
+        column: String,
+    },
+    Add {
+        key: Map<String, Value>,
+        row: Map<String, Value>,
+    },
+    Remove {
+        key: Map<String, Value>,
+        row: Map<String, Value>,
+    },
+    Replace {
+        key: Map<String, Value>,
+        changes: Vec<CellChange>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CellChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+pub fn process_csv_diff(opts: &CsvDiffOpts) -> Result<String> {
+    let mut old = build_csv_reader(&opts.old, &opts.reader)?;
+    let mut new = build_csv_reader(&opts.new, &opts.reader)?;
+    let old_header = old.headers()?;
+    let new_header = new.headers()?;
+    let diffs = diff_records(
+        &old_header,
+        old.records(),
+        &new_header,
+        new.records(),
+        &opts.key,
+    )?;
+    let report = match opts.format {
+        DiffFormat::Json => serde_json::to_string_pretty(&diffs)? + "\n",
+        DiffFormat::Text => render_diff(&diffs, !opts.no_color && stdout().is_terminal()),
+    };
+    Ok(report)
+}
+
+/// compare the snapshots by key, only the columns in both files are compared
+pub fn diff_records(
+    old_header: &StringRecord,
+    old_records: impl Iterator<Item = csv::Result<StringRecord>>,
+    new_header: &StringRecord,
+    new_records: impl Iterator<Item = csv::Result<StringRecord>>,
+    keys: &[String],
+) -> Result<Vec<DiffRecord>> {
+    let mut diffs = vec![];
+    for column in old_header.iter() {
+        if !new_header.iter().any(|h| h == column) {
+            diffs.push(DiffRecord::RemoveColumn {
+                column: column.to_string(),
+            });
+        }
+    }
+    for column in new_header.iter() {
+        if !old_header.iter().any(|h| h == column) {
+            diffs.push(DiffRecord::AddColumn {
+                column: column.to_string(),
+            });
+        }
+    }
+    // (列名, 旧的下标, 新的下标)
+    let common = old_header
+        .iter()
+        .enumerate()
+        .filter_map(|(old_idx, name)| {
+            let new_idx = new_header.iter().position(|h| h == name)?;
+            Some((name, old_idx, new_idx))
+        })
+        .collect::<Vec<_>>();
+    let old_keys = key_indexes(old_header, keys)?;
+    let new_keys = key_indexes(new_header, keys)?;
+
+    // 旧文件按key建索引, 新文件流式比较
+    let old_records = old_records.collect::<Result<Vec<_>, _>>()?;
+    let mut index = HashMap::new();
+    for (idx, record) in old_records.iter().enumerate() {
+        if index.insert(key_of(record, &old_keys), idx).is_some() {
+            anyhow::bail!("duplicate key in old file: {:?}", key_of(record, &old_keys));
+        }
+    }
+    let mut seen = vec![false; old_records.len()];
+    let mut new_seen = HashMap::new();
+    for record in new_records {
+        let record = record?;
+        let key = key_of(&record, &new_keys);
+        if new_seen.insert(key.clone(), ()).is_some() {
+            anyhow::bail!("duplicate key in new file: {:?}", key);
+        }
+        match index.get(&key) {
+            Some(&idx) => {
+                seen[idx] = true;
+                let changes = common
+                    .iter()
+                    .filter_map(|&(name, old_idx, new_idx)| {
+                        let old = old_records[idx].get(old_idx).unwrap_or_default();
+                        let new = record.get(new_idx).unwrap_or_default();
+                        (old != new).then(|| CellChange {
+                            column: name.to_string(),
+                            old: old.to_string(),
+                            new: new.to_string(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if !changes.is_empty() {
+                    diffs.push(DiffRecord::Replace {
+                        key: key_map(keys, &key),
+                        changes,
+                    });
+                }
+            }
+            None => diffs.push(DiffRecord::Add {
+                key: key_map(keys, &key),
+                row: record_map(new_header, &record),
+            }),
+        }
+    }
+    for (record, _) in old_records.iter().zip(seen).filter(|(_, seen)| !seen) {
+        diffs.push(DiffRecord::Remove {
+            key: key_map(keys, &key_of(record, &old_keys)),
+            row: record_map(old_header, record),
+        });
+    }
+    Ok(diffs)
+}
+
+fn render_diff(diffs: &[DiffRecord], color: bool) -> String {
+    let paint = |color_code: &str, s: &str| {
+        if color {
+            format!("{}{}{}", color_code, s, RESET)
+        } else {
+            s.to_string()
+        }
+    };
+    let display_key = |key: &Map<String, Value>| {
+        key.iter()
+            .map(|(k, v)| format!("{}={}", k, v.as_str().unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    let mut lines = vec![];
+    for diff in diffs {
+        match diff {
+            DiffRecord::AddColumn { column } => {
+                lines.push(paint(GREEN, &format!("+ column {}", column)));
+            }
+            DiffRecord::RemoveColumn { column } => {
+                lines.push(paint(RED, &format!("- column {}", column)));
+            }
+            DiffRecord::Add { key, .. } => {
+                added += 1;
+                lines.push(paint(GREEN, &format!("+ {}", display_key(key))));
+            }
+            DiffRecord::Remove { key, .. } => {
+                removed += 1;
+                lines.push(paint(RED, &format!("- {}", display_key(key))));
+            }
+            DiffRecord::Replace { key, changes } => {
+                changed += 1;
+                lines.push(paint(YELLOW, &format!("~ {}", display_key(key))));
+                for change in changes {
+                    lines.push(format!(
+                        "    {}: {} -> {}",
+                        change.column,
+                        paint(RED, &change.old),
+                        paint(GREEN, &change.new)
+                    ));
+                }
+            }
+        }
+    }
+    lines.push(format!(
+        "{} added, {} removed, {} changed",
+        added, removed, changed
+    ));
+    lines.join("\n") + "\n"
+}
+
+fn key_indexes(header: &StringRecord, keys: &[String]) -> Result<Vec<usize>> {
+    keys.iter()
+        .map(|key| {
+            header
+                .iter()
+                .position(|h| h == key)
+                .ok_or_else(|| anyhow::anyhow!("key column not found: {}", key))
+        })
+        .collect()
+}
+
+fn key_of(record: &StringRecord, indexes: &[usize]) -> Vec<String> {
+    indexes
+        .iter()
+        .map(|&idx| record.get(idx).unwrap_or_default().to_string())
+        .collect()
+}
+
+fn key_map(keys: &[String], values: &[String]) -> Map<String, Value> {
+    keys.iter()
+        .zip(values)
+        .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+        .collect()
+}
+
+fn record_map(header: &StringRecord, record: &StringRecord) -> Map<String, Value> {
+    header
+        .iter()
+        .zip(record.iter())
+        .map(|(k, v)| (k.to_string(), Value::from(v)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_records() -> Result<()> {
+        let mut old = csv::Reader::from_reader("id,name,age\n1,a,10\n2,b,20\n3,c,30\n".as_bytes());
+        let mut new = csv::Reader::from_reader("id,name,city\n3,c,x\n1,A,y\n4,d,z\n".as_bytes());
+        let old_header = old.headers()?.clone();
+        let new_header = new.headers()?.clone();
+        let diffs = diff_records(
+            &old_header,
+            old.records(),
+            &new_header,
+            new.records(),
+            &["id".to_string()],
+        )?;
+        let ops = diffs
+            .iter()
+            .map(|d| serde_json::to_value(d).map(|v| v["op"].clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            ops,
+            ["remove_column", "add_column", "replace", "add", "remove"]
+        );
+        assert_eq!(
+            diffs[2],
+            DiffRecord::Replace {
+                key: key_map(&["id".to_string()], &["1".to_string()]),
+                changes: vec![CellChange {
+                    column: "name".to_string(),
+                    old: "a".to_string(),
+                    new: "A".to_string(),
+                }],
+            }
+        );
+
+        let report = render_diff(&diffs, false);
+        assert!(report.contains("~ id=1\n    name: a -> A\n"));
+        assert!(report.ends_with("1 added, 1 removed, 1 changed\n"));
+        Ok(())
+    }
+}
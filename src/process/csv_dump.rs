@@ -0,0 +1,221 @@
+use super::{
+    apply_type_overrides, build_csv_reader, infer_column_types, insert_table, quote_ident,
+    sql_type, typed_value,
+};
+use crate::{
+    cli::csv_opts::{ColumnType, CsvDumpOpts, SqlDialect},
+    utils::get_writer,
+};
+use anyhow::Result;
+use csv::StringRecord;
+use rusqlite::Connection;
+use serde_json::Value;
+use std::{io::Write, path::Path};
+
+pub fn process_csv_dump(opts: &CsvDumpOpts) -> Result<()> {
+    let table = match &opts.table {
+        Some(table) => table.clone(),
+        None if opts.input == "-" => anyhow::bail!("--table is required when reading from stdin"),
+        None => Path::new(&opts.input)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow::anyhow!("can't get the table name from {}", opts.input))?
+            .to_string(),
+    };
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    // 推断类型需要所有的值, 所以先全部读进内存
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    let mut types = infer_column_types(&records, header.len());
+    apply_type_overrides(&header, &mut types, &opts.types)?;
+
+    if let Some(path) = &opts.sqlite {
+        let conn = Connection::open(path)?;
+        if opts.replace {
+            conn.execute(&format!("DROP TABLE IF EXISTS {}", quote_ident(&table)), [])?;
+        }
+        insert_table(&conn, &table, &header, &records, &types)?;
+        eprintln!("{} rows inserted into {}", records.len(), path);
+        return Ok(());
+    }
+
+    let dump = SqlDump {
+        dialect: opts.dialect,
+        table: &table,
+        header: &header,
+        types: &types,
+    };
+    let mut writer = get_writer(&opts.output)?;
+    write!(writer, "{}", dump.create_table(opts.replace))?;
+    // 在一个事务里插入, 失败的时候不会只插入一部分
+    writeln!(writer, "BEGIN;")?;
+    for batch in records.chunks(opts.batch_size as usize) {
+        write!(writer, "{}", dump.insert(batch))?;
+    }
+    writeln!(writer, "COMMIT;")?;
+    writer.flush()?;
+    eprintln!("{} rows dumped", records.len());
+    Ok(())
+}
+
+/// render the csv as sql statements of a dialect
+pub struct SqlDump<'a> {
+    pub dialect: SqlDialect,
+    pub table: &'a str,
+    pub header: &'a StringRecord,
+    pub types: &'a [ColumnType],
+}
+
+impl SqlDump<'_> {
+    /// `CREATE TABLE`, with `DROP TABLE IF EXISTS` before it when `replace`
+    pub fn create_table(&self, replace: bool) -> String {
+        let mut sql = String::new();
+        if replace {
+            sql.push_str(&format!(
+                "DROP TABLE IF EXISTS {};\n",
+                self.ident(self.table)
+            ));
+        }
+        let columns = self
+            .header
+            .iter()
+            .zip(self.types)
+            .map(|(name, ty)| format!("  {} {}", self.ident(name), self.column_type(*ty)))
+            .collect::<Vec<_>>();
+        sql.push_str(&format!(
+            "CREATE TABLE {} (\n{}\n);\n",
+            self.ident(self.table),
+            columns.join(",\n")
+        ));
+        sql
+    }
+
+    /// one `INSERT` statement with all the records
+    pub fn insert(&self, records: &[StringRecord]) -> String {
+        let columns = self
+            .header
+            .iter()
+            .map(|name| self.ident(name))
+            .collect::<Vec<_>>();
+        let rows = records
+            .iter()
+            .map(|record| {
+                let values = self
+                    .types
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, ty)| {
+                        self.literal(typed_value(record.get(idx).unwrap_or_default(), *ty))
+                    })
+                    .collect::<Vec<_>>();
+                format!("  ({})", values.join(", "))
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "INSERT INTO {} ({}) VALUES\n{};\n",
+            self.ident(self.table),
+            columns.join(", "),
+            rows.join(",\n")
+        )
+    }
+
+    fn ident(&self, name: &str) -> String {
+        match self.dialect {
+            SqlDialect::Mysql => format!("`{}`", name.replace('`', "``")),
+            SqlDialect::Postgres | SqlDialect::Sqlite => quote_ident(name),
+        }
+    }
+
+    fn column_type(&self, ty: ColumnType) -> &'static str {
+        match (self.dialect, ty) {
+            (SqlDialect::Sqlite, ty) => sql_type(ty),
+            (_, ColumnType::Integer) => "BIGINT",
+            (SqlDialect::Postgres, ColumnType::Float) => "DOUBLE PRECISION",
+            (_, ColumnType::Float) => "DOUBLE",
+            (_, ColumnType::Boolean) => "BOOLEAN",
+            (_, ColumnType::Null | ColumnType::String) => "TEXT",
+        }
+    }
+
+    fn literal(&self, value: Value) -> String {
+        match value {
+            Value::Null => "NULL".to_string(),
+            // sqlite没有boolean类型, 和load_csv_table一样存为0和1
+            Value::Bool(b) if self.dialect == SqlDialect::Sqlite => (b as i64).to_string(),
+            Value::Bool(b) => b.to_string().to_uppercase(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => {
+                // mysql默认把反斜杠当作转义字符
+                let s = match self.dialect {
+                    SqlDialect::Mysql => s.replace('\\', "\\\\"),
+                    _ => s,
+                };
+                format!("'{}'", s.replace('\'', "''"))
+            }
+            other => format!("'{}'", other.to_string().replace('\'', "''")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sql_dump() -> Result<()> {
+        let header = StringRecord::from(vec!["name", "kit", "height", "starter"]);
+        let records = vec![
+            StringRecord::from(vec!["O'Neil", "1", "1.8", "true"]),
+            StringRecord::from(vec!["a\\b", "", "2", "false"]),
+            StringRecord::from(vec!["c", "3", "", ""]),
+        ];
+        let types = infer_column_types(&records, header.len());
+        let dump = SqlDump {
+            dialect: SqlDialect::Postgres,
+            table: "players",
+            header: &header,
+            types: &types,
+        };
+        assert_eq!(
+            dump.create_table(true),
+            "DROP TABLE IF EXISTS \"players\";\nCREATE TABLE \"players\" (\n  \"name\" TEXT,\n  \
+             \"kit\" BIGINT,\n  \"height\" DOUBLE PRECISION,\n  \"starter\" BOOLEAN\n);\n"
+        );
+        assert_eq!(
+            dump.insert(&records[..2]),
+            "INSERT INTO \"players\" (\"name\", \"kit\", \"height\", \"starter\") VALUES\n  \
+             ('O''Neil', 1, 1.8, TRUE),\n  ('a\\b', NULL, 2.0, FALSE);\n"
+        );
+        let dump = SqlDump {
+            dialect: SqlDialect::Mysql,
+            ..dump
+        };
+        assert!(dump
+            .insert(&records[1..2])
+            .starts_with("INSERT INTO `players` (`name`"));
+        assert!(dump
+            .insert(&records[1..2])
+            .contains("('a\\\\b', NULL, 2.0, FALSE)"));
+
+        // sqlite方言的sql可以直接执行
+        let dump = SqlDump {
+            dialect: SqlDialect::Sqlite,
+            ..dump
+        };
+        let conn = Connection::open_in_memory()?;
+        let sql = format!(
+            "{}{}{}",
+            dump.create_table(false),
+            dump.insert(&records[..2]),
+            dump.insert(&records[2..])
+        );
+        conn.execute_batch(&sql)?;
+        let (count, kits, starters): (i64, i64, i64) = conn.query_row(
+            "SELECT count(*), sum(kit), sum(starter) FROM players",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        assert_eq!((count, kits, starters), (3, 4, 1));
+        Ok(())
+    }
+}
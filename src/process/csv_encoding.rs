@@ -0,0 +1,237 @@
+use anyhow::Result;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{CoderResult, Encoder, Encoding, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, Cursor, Read, Write};
+
+/// 自动检测编码时读取的字节数
+const SNIFF_SIZE: u64 = 64 * 1024;
+
+/// detect the encoding by BOM first, then guess by the content
+pub fn detect_encoding(prefix: &[u8], last: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(prefix) {
+        return encoding;
+    }
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Allow);
+    detector.feed(prefix, last);
+    detector.guess(None, Utf8Detection::Allow)
+}
+
+/// wrap the reader to decode it into utf-8, the BOM is stripped.
+/// the encoding is detected from the first 64KB if not specified
+pub fn decode_reader(
+    mut reader: impl Read + 'static,
+    encoding: Option<&'static Encoding>,
+) -> Result<(Box<dyn Read>, &'static Encoding)> {
+    let mut prefix = vec![];
+    reader.by_ref().take(SNIFF_SIZE).read_to_end(&mut prefix)?;
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => detect_encoding(&prefix, (prefix.len() as u64) < SNIFF_SIZE),
+    };
+    let reader = Cursor::new(prefix).chain(reader);
+    let mut builder = DecodeReaderBytesBuilder::new();
+    builder.strip_bom(true);
+    if encoding == UTF_8 {
+        // utf-8不需要转码, 非法的字节交给csv处理
+        builder.utf8_passthru(true);
+    } else {
+        builder.encoding(Some(encoding)).bom_override(true);
+    }
+    Ok((Box::new(builder.build(reader)), encoding))
+}
+
+/// transcode the utf-8 output into another encoding,
+/// characters that can't be encoded are written as html numeric references, eg: `&#9731;`
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    encoder: Encoder,
+    /// 上一次write剩下的不完整的utf-8字节
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(inner: W, encoding: &'static Encoding) -> Result<Self> {
+        if encoding.output_encoding() != encoding {
+            anyhow::bail!("encoding {} is not supported for output", encoding.name());
+        }
+        Ok(Self {
+            inner,
+            encoder: encoding.new_encoder(),
+            pending: vec![],
+            finished: false,
+        })
+    }
+
+    /// encode the pending bytes as the end of the output and flush, must be called after the
+    /// last write, the errors are discarded if it's left to `drop`
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        self.encode(true)?;
+        self.inner.flush()
+    }
+
+    fn encode(&mut self, last: bool) -> io::Result<()> {
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() && !last => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let mut src = std::str::from_utf8(&self.pending[..valid]).unwrap_or_default();
+        let mut out = Vec::with_capacity(valid + 16);
+        loop {
+            let (result, read, _) = self.encoder.encode_from_utf8_to_vec(src, &mut out, last);
+            src = &src[read..];
+            match result {
+                CoderResult::InputEmpty => break,
+                CoderResult::OutputFull => out.reserve(src.len() * 2 + 16),
+            }
+        }
+        self.pending.drain(..valid);
+        self.inner.write_all(&out)
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.encode(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncodingWriter<W> {
+    fn drop(&mut self) {
+        // 有状态的编码(eg: ISO-2022-JP)最后需要切回ASCII
+        if !self.finished {
+            let _ = self.finish();
+        }
+    }
+}
+
+/// a writer with an explicit end, the errors of the last write are returned by `finish`
+pub trait FinishWrite: Write {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl FinishWrite for &mut Vec<u8> {}
+
+/// the output of the row writers, transcoded if `--out-encoding` is given
+pub enum Output {
+    Plain(Box<dyn Write>),
+    Encoded(EncodingWriter<Box<dyn Write>>),
+}
+
+impl From<Box<dyn Write>> for Output {
+    fn from(writer: Box<dyn Write>) -> Self {
+        Output::Plain(writer)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Encoded(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Encoded(writer) => writer.flush(),
+        }
+    }
+}
+
+impl FinishWrite for Output {
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Encoded(writer) => writer.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, ISO_2022_JP, SHIFT_JIS, UTF_16LE};
+
+    #[test]
+    fn test_decode_reader() -> Result<()> {
+        let text = "名字,国家\n布冯,意大利\n";
+        let (gbk, _, _) = GBK.encode(text);
+        let (mut reader, encoding) = decode_reader(Cursor::new(gbk.into_owned()), None)?;
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded)?;
+        assert_eq!(encoding, GBK);
+        assert_eq!(decoded, text);
+
+        // utf-16 with BOM
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend(text.encode_utf16().flat_map(|c| c.to_le_bytes()));
+        let (mut reader, encoding) = decode_reader(Cursor::new(utf16), None)?;
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded)?;
+        assert_eq!(encoding, UTF_16LE);
+        assert_eq!(decoded, text);
+
+        // utf-8 BOM is stripped
+        let bom = [b"\xEF\xBB\xBF".as_slice(), text.as_bytes()].concat();
+        let (mut reader, _) = decode_reader(Cursor::new(bom), Some(UTF_8))?;
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded)?;
+        assert_eq!(decoded, text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoding_writer() -> Result<()> {
+        let text = "名前,国\nブッフォン,イタリア\n";
+        let mut out = vec![];
+        {
+            let mut writer = EncodingWriter::new(&mut out, SHIFT_JIS)?;
+            // 故意把一个字符拆成两次写
+            let bytes = text.as_bytes();
+            writer.write_all(&bytes[..4])?;
+            writer.write_all(&bytes[4..])?;
+        }
+        let (decoded, _, errors) = SHIFT_JIS.decode(&out);
+        assert!(!errors);
+        assert_eq!(decoded, text);
+        assert!(EncodingWriter::new(vec![], UTF_16LE).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoding_writer_finish() -> Result<()> {
+        // ISO-2022-JP最后切回ASCII的转义序列是finish写的
+        let mut out = vec![];
+        let mut writer = EncodingWriter::new(&mut out, ISO_2022_JP)?;
+        writer.write_all("イタリア".as_bytes())?;
+        writer.finish()?;
+        drop(writer);
+        assert!(out.ends_with(b"\x1B(B"));
+        assert_eq!(ISO_2022_JP.decode(&out).0, "イタリア");
+
+        // 最后一个字符不完整, finish返回错误而不是在drop里丢掉
+        let mut writer = EncodingWriter::new(vec![], SHIFT_JIS)?;
+        writer.write_all(&"イ".as_bytes()[..2])?;
+        assert!(writer.finish().is_err());
+        let mut output = Output::Encoded(EncodingWriter::new(
+            Box::new(vec![]) as Box<dyn Write>,
+            SHIFT_JIS,
+        )?);
+        output.write_all(&"イ".as_bytes()[..2])?;
+        assert!(output.finish().is_err());
+        Ok(())
+    }
+}
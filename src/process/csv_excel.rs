@@ -0,0 +1,286 @@
+use super::{column_names, merge_column_type};
+use crate::cli::csv_opts::{ColumnType, CsvReaderOpts};
+use anyhow::{Context, Result};
+use calamine::{open_workbook_auto, Data, Reader};
+use csv::StringRecord;
+use std::path::Path;
+
+/// 用calamine读取的表格文件
+const SPREADSHEET_EXTENSIONS: [&str; 6] = ["xlsx", "xlsm", "xlsb", "xla", "xls", "ods"];
+
+pub fn is_spreadsheet(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            SPREADSHEET_EXTENSIONS
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(ext))
+        })
+}
+
+/// the cells of a sheet as csv records, the column types come from the typed cells
+#[derive(Debug)]
+pub struct Sheet {
+    pub name: String,
+    pub header: StringRecord,
+    pub records: Vec<StringRecord>,
+    pub types: Vec<ColumnType>,
+}
+
+/// read a sheet of a xlsx/xls/ods workbook, `sheet` is the name or the 0-based index (default the first),
+/// `range` is in A1 notation, eg: "A1:D20", or "B3" to read from B3 to the end of the sheet
+pub fn read_sheet(
+    path: &str,
+    sheet: Option<&str>,
+    range: Option<&str>,
+    opts: &CsvReaderOpts,
+) -> Result<Sheet> {
+    let mut workbook =
+        open_workbook_auto(path).with_context(|| format!("can't open workbook: {}", path))?;
+    let names = workbook.sheet_names();
+    let name = match sheet {
+        None => names.first().cloned(),
+        Some(sheet) if names.iter().any(|name| name == sheet) => Some(sheet.to_string()),
+        Some(sheet) => sheet
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| names.get(idx))
+            .cloned(),
+    }
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "sheet not found: {}, the workbook has: {}",
+            sheet.unwrap_or_default(),
+            names.join(", ")
+        )
+    })?;
+    let mut cells = workbook.worksheet_range(&name)?;
+    if let Some(range) = range {
+        let (start, end) = parse_cell_range(range)?;
+        // 空的表格读出来也是空的
+        if let (Some(used_start), Some(used_end)) = (cells.start(), cells.end()) {
+            // 只有开始的单元格的时候读到表格的最后, 超出已使用区域的部分不读
+            let end = end.unwrap_or(used_end);
+            let start = (start.0.max(used_start.0), start.1.max(used_start.1));
+            let end = (end.0.min(used_end.0), end.1.min(used_end.1));
+            if start.0 > end.0 || start.1 > end.1 {
+                anyhow::bail!(
+                    "range {} is outside the used area {}:{} of sheet {}",
+                    range,
+                    cell_name(used_start),
+                    cell_name(used_end),
+                    name
+                );
+            }
+            cells = cells.range(start, end);
+        }
+    }
+
+    let mut rows = cells.rows();
+    let header = match opts.header {
+        true => rows
+            .next()
+            .map(|row| row.iter().map(|cell| cell_value(cell).0).collect()),
+        false => None,
+    };
+    let mut types = vec![ColumnType::Null; cells.width()];
+    let mut records = vec![];
+    // 表格里的空行一般只是格式, 和csv的空行一样跳过
+    for row in rows.filter(|row| row.iter().any(|cell| !matches!(cell, Data::Empty))) {
+        let mut record = StringRecord::with_capacity(0, row.len());
+        for (ty, cell) in types.iter_mut().zip(row) {
+            let (text, cell_type) = cell_value(cell);
+            *ty = merge_column_type(*ty, cell_type);
+            record.push_field(&text);
+        }
+        records.push(record);
+    }
+    let header = column_names(header.as_ref(), cells.width(), opts)?;
+    Ok(Sheet {
+        name,
+        header,
+        records,
+        types,
+    })
+}
+
+/// the text and the type of a cell, numbers without fraction are integers,
+/// dates are ISO-8601 strings
+fn cell_value(cell: &Data) -> (String, ColumnType) {
+    match cell {
+        Data::Empty => (String::new(), ColumnType::Null),
+        Data::Int(v) => (v.to_string(), ColumnType::Integer),
+        // xlsx里的数字都是浮点数, 37.0按整数输出
+        Data::Float(v) if v.fract() == 0.0 && v.abs() < 1e15 => {
+            ((*v as i64).to_string(), ColumnType::Integer)
+        }
+        Data::Float(v) => (v.to_string(), ColumnType::Float),
+        Data::Bool(v) => (v.to_string(), ColumnType::Boolean),
+        Data::DateTime(v) if v.is_datetime() => {
+            let text = v.as_datetime().map(|datetime| {
+                if datetime.time() == chrono::NaiveTime::MIN {
+                    datetime.format("%Y-%m-%d").to_string()
+                } else {
+                    datetime.format("%Y-%m-%dT%H:%M:%S").to_string()
+                }
+            });
+            (text.unwrap_or_default(), ColumnType::String)
+        }
+        Data::DateTime(v) => {
+            let text = v.as_duration().map(|duration| duration.to_string());
+            (text.unwrap_or_default(), ColumnType::String)
+        }
+        Data::String(v) | Data::DateTimeIso(v) | Data::DurationIso(v) => {
+            (v.clone(), ColumnType::String)
+        }
+        Data::Error(e) => (e.to_string(), ColumnType::String),
+    }
+}
+
+/// (row, column), 0-based
+type CellPosition = (u32, u32);
+
+/// "A1:D20" is ((0, 0), Some((19, 3))), "B3" is ((2, 1), None)
+fn parse_cell_range(range: &str) -> Result<(CellPosition, Option<CellPosition>)> {
+    let (start, end) = match range.split_once(':') {
+        Some((start, end)) => (start, Some(end)),
+        None => (range, None),
+    };
+    let start = parse_cell(start)?;
+    let end = end.map(parse_cell).transpose()?;
+    if let Some(end) = end {
+        if end.0 < start.0 || end.1 < start.1 {
+            anyhow::bail!("invalid cell range: {}", range);
+        }
+    }
+    Ok((start, end))
+}
+
+fn parse_cell(cell: &str) -> Result<CellPosition> {
+    let cell = cell.trim();
+    let split = cell
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(cell.len());
+    let (letters, digits) = cell.split_at(split);
+    let column = letters.chars().try_fold(0u32, |acc, c| {
+        acc.checked_mul(26)?
+            .checked_add(c.to_ascii_uppercase() as u32 - 'A' as u32 + 1)
+    });
+    match (column, digits.parse::<u32>()) {
+        (Some(column), Ok(row)) if column > 0 && row > 0 => Ok((row - 1, column - 1)),
+        _ => anyhow::bail!("invalid cell: {}, eg: A1, AB12", cell),
+    }
+}
+
+/// the A1 notation of a cell
+fn cell_name((row, column): CellPosition) -> String {
+    let mut letters = vec![];
+    let mut column = column + 1;
+    while column > 0 {
+        column -= 1;
+        letters.push((b'A' + (column % 26) as u8) as char);
+        column /= 26;
+    }
+    letters.iter().rev().collect::<String>() + &(row + 1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_read_sheet() -> Result<()> {
+        let opts = CsvReaderOpts::parse_from(["csv"]);
+        let sheet = read_sheet("assets/juventus.ods", None, None, &opts)?;
+        assert_eq!(sheet.name, "players");
+        assert_eq!(
+            sheet.header,
+            StringRecord::from(vec![
+                "Name",
+                "Position",
+                "DOB",
+                "Nationality",
+                "Kit Number",
+                "Height",
+                "Starter"
+            ])
+        );
+        // 空行被跳过
+        assert_eq!(sheet.records.len(), 3);
+        assert_eq!(
+            sheet.records[1],
+            StringRecord::from(vec![
+                "Mattia Perin",
+                "Goalkeeper",
+                "1992-11-10",
+                "Italy",
+                "37",
+                "1.88",
+                "false"
+            ])
+        );
+        assert_eq!(
+            sheet.types,
+            vec![
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Boolean
+            ]
+        );
+
+        let opts = CsvReaderOpts::parse_from(["csv", "--no-header"]);
+        let sheet = read_sheet("assets/juventus.ods", Some("0"), Some("E2:F3"), &opts)?;
+        assert_eq!(sheet.header, StringRecord::from(vec!["col1", "col2"]));
+        assert_eq!(sheet.records[0], StringRecord::from(vec!["1", "1.95"]));
+        assert_eq!(sheet.records.len(), 2);
+
+        let sheet = read_sheet("assets/juventus.ods", Some("notes"), None, &opts)?;
+        assert_eq!(sheet.records.len(), 2);
+        assert!(read_sheet("assets/juventus.ods", Some("2"), None, &opts).is_err());
+
+        // 超出已使用区域的部分被截掉, 完全在外面的报错
+        let sheet = read_sheet("assets/juventus.ods", Some("0"), Some("F3:Z100"), &opts)?;
+        assert_eq!(sheet.header, StringRecord::from(vec!["col1", "col2"]));
+        assert_eq!(sheet.records[1], StringRecord::from(vec!["1.92", "true"]));
+        assert_eq!(sheet.records.len(), 2);
+        assert!(read_sheet("assets/juventus.ods", Some("0"), Some("A100"), &opts).is_err());
+        assert!(read_sheet("assets/juventus.ods", Some("0"), Some("H2"), &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cell_range() -> Result<()> {
+        assert_eq!(parse_cell_range("A1:D20")?, ((0, 0), Some((19, 3))));
+        assert_eq!(parse_cell_range("b3")?, ((2, 1), None));
+        assert_eq!(parse_cell_range("AA10:AB11")?, ((9, 26), Some((10, 27))));
+        assert!(parse_cell_range("A0").is_err());
+        assert!(parse_cell_range("12").is_err());
+        assert!(parse_cell_range("C3:A1").is_err());
+        assert_eq!(cell_name((19, 3)), "D20");
+        assert_eq!(cell_name((9, 27)), "AB10");
+        Ok(())
+    }
+
+    #[test]
+    fn test_cell_value() {
+        assert_eq!(
+            cell_value(&Data::Float(37.0)),
+            ("37".to_string(), ColumnType::Integer)
+        );
+        assert_eq!(
+            cell_value(&Data::Float(1.5)),
+            ("1.5".to_string(), ColumnType::Float)
+        );
+        assert_eq!(
+            cell_value(&Data::Bool(true)),
+            ("true".to_string(), ColumnType::Boolean)
+        );
+        assert_eq!(cell_value(&Data::Empty), (String::new(), ColumnType::Null));
+    }
+}
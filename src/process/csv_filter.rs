@@ -0,0 +1,494 @@
+use super::parse_number;
+use crate::cli::csv_opts::SortKey;
+use anyhow::Result;
+use csv::StringRecord;
+use std::cmp::Ordering;
+
+/// a tiny expression language used by `--where`, eg:
+/// `Position == 'Goalkeeper' && Kit Number > 10`
+///
+/// - column: bare words (spaces allowed), or quoted with `"` / `` ` ``
+/// - literal: `'string'`, numbers, true/false
+/// - operators: `== != > >= < <= && || !` and parentheses
+/// - arithmetic: `+ - * /`, `+` concatenates strings, `-` must be surrounded by spaces
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(usize),
+    Literal(String),
+    Arith(Box<Expr>, ArithOp, Box<Expr>),
+    Compare(Box<Expr>, CmpOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// bare word, column or number literal
+    Word(String),
+    /// `"Kit Number"` or `` `Kit Number` ``
+    Column(String),
+    /// `'Goalkeeper'`
+    Str(String),
+    Cmp(CmpOp),
+    Arith(ArithOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Expr {
+    /// parse the expression, column names are resolved to indexes of the header
+    pub fn parse(input: &str, header: &StringRecord) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            header,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            anyhow::bail!("unexpected token in expression: {:?}", token);
+        }
+        Ok(expr)
+    }
+
+    /// evaluate the expression on a record
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Arith(..) => is_truthy(&self.value(record)),
+            Expr::Compare(left, op, right) => {
+                let ord = compare_values(&left.value(record), &right.value(record));
+                match op {
+                    CmpOp::Eq => ord == Ordering::Equal,
+                    CmpOp::Ne => ord != Ordering::Equal,
+                    CmpOp::Gt => ord == Ordering::Greater,
+                    CmpOp::Ge => ord != Ordering::Less,
+                    CmpOp::Lt => ord == Ordering::Less,
+                    CmpOp::Le => ord != Ordering::Greater,
+                }
+            }
+            Expr::And(left, right) => left.matches(record) && right.matches(record),
+            Expr::Or(left, right) => left.matches(record) || right.matches(record),
+            Expr::Not(expr) => !expr.matches(record),
+        }
+    }
+
+    /// evaluate the expression to a cell, conditions are "true" or "false"
+    pub fn value(&self, record: &StringRecord) -> String {
+        match self {
+            Expr::Column(idx) => record.get(*idx).unwrap_or_default().to_string(),
+            Expr::Literal(s) => s.clone(),
+            Expr::Arith(left, op, right) => arith(&left.value(record), *op, &right.value(record)),
+            _ => self.matches(record).to_string(),
+        }
+    }
+}
+
+/// integers stay integers except for division, non-numbers can only be concatenated by `+`,
+/// otherwise the result is empty
+fn arith(a: &str, op: ArithOp, b: &str) -> String {
+    let (a_trim, b_trim) = (a.trim(), b.trim());
+    if let (Ok(a), Ok(b), false) = (
+        a_trim.parse::<i64>(),
+        b_trim.parse::<i64>(),
+        op == ArithOp::Div,
+    ) {
+        let result = match op {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            _ => a.checked_mul(b),
+        };
+        if let Some(result) = result {
+            return result.to_string();
+        }
+    }
+    match (a_trim.parse::<f64>(), b_trim.parse::<f64>()) {
+        (Ok(a), Ok(b)) => {
+            let result = match op {
+                ArithOp::Add => a + b,
+                ArithOp::Sub => a - b,
+                ArithOp::Mul => a * b,
+                ArithOp::Div => a / b,
+            };
+            if result.is_finite() {
+                result.to_string()
+            } else {
+                String::new()
+            }
+        }
+        _ if op == ArithOp::Add => format!("{}{}", a, b),
+        _ => String::new(),
+    }
+}
+
+/// compare two cells, numbers are compared numerically and come before strings,
+/// strings are compared lexically, so it's a total order and can be used to sort
+pub fn compare_values(a: &str, b: &str) -> Ordering {
+    match (parse_number(a), parse_number(b)) {
+        // parse_number只返回有限的数, 不会有NaN
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// resolve the sort keys to (column index, desc)
+pub fn resolve_sort_keys(header: &StringRecord, keys: &[SortKey]) -> Result<Vec<(usize, bool)>> {
+    keys.iter()
+        .map(|key| {
+            let idx = header
+                .iter()
+                .position(|h| h == key.column)
+                .ok_or_else(|| anyhow::anyhow!("column not found: {}", key.column))?;
+            Ok((idx, key.desc))
+        })
+        .collect()
+}
+
+/// sort records by the resolved keys, the sort is stable
+pub fn sort_records(records: &mut [StringRecord], keys: &[(usize, bool)]) {
+    records.sort_by(|a, b| {
+        keys.iter()
+            .map(|&(idx, desc)| {
+                let ord = compare_values(
+                    a.get(idx).unwrap_or_default(),
+                    b.get(idx).unwrap_or_default(),
+                );
+                if desc {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false"))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' {
+                    Token::LParen
+                } else {
+                    Token::RParen
+                });
+            }
+            '\'' | '"' | '`' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => s.extend(chars.next()),
+                        Some(ch) if ch == c => break,
+                        Some(ch) => s.push(ch),
+                        None => anyhow::bail!("unterminated quote in expression: {}", input),
+                    }
+                }
+                tokens.push(if c == '\'' {
+                    Token::Str(s)
+                } else {
+                    Token::Column(s)
+                });
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    anyhow::bail!("expect `{}{}` in expression", c, c);
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '+' | '*' | '/' => {
+                chars.next();
+                tokens.push(Token::Arith(match c {
+                    '+' => ArithOp::Add,
+                    '*' => ArithOp::Mul,
+                    _ => ArithOp::Div,
+                }));
+            }
+            // `a - b` 是减法, `-5` 和 `Date-Of-Birth` 还是一个词
+            '-' if chars.clone().nth(1).is_some_and(char::is_whitespace) => {
+                chars.next();
+                tokens.push(Token::Arith(ArithOp::Sub));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if_eq(&'=').is_some();
+                let token = match (c, eq) {
+                    ('=', true) => Token::Cmp(CmpOp::Eq),
+                    ('!', true) => Token::Cmp(CmpOp::Ne),
+                    ('!', false) => Token::Not,
+                    ('<', true) => Token::Cmp(CmpOp::Le),
+                    ('<', false) => Token::Cmp(CmpOp::Lt),
+                    ('>', true) => Token::Cmp(CmpOp::Ge),
+                    ('>', false) => Token::Cmp(CmpOp::Gt),
+                    _ => anyhow::bail!("expect `==` in expression"),
+                };
+                tokens.push(token);
+            }
+            _ => {
+                // 不带引号的列名可以包含空格, eg: Kit Number > 10
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    let minus = ch == '-'
+                        && word.ends_with(char::is_whitespace)
+                        && chars.clone().nth(1).is_some_and(char::is_whitespace);
+                    if "()'\"`&|=!<>+*/".contains(ch) || minus {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word.trim().to_string()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    header: &'a StringRecord,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        let left = self.parse_sum()?;
+        if let Some(Token::Cmp(op)) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            let right = self.parse_sum()?;
+            return Ok(Expr::Compare(Box::new(left), op, Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_product()?;
+        while let Some(Token::Arith(op @ (ArithOp::Add | ArithOp::Sub))) =
+            self.tokens.get(self.pos).cloned()
+        {
+            self.pos += 1;
+            expr = Expr::Arith(Box::new(expr), op, Box::new(self.parse_product()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_operand()?;
+        while let Some(Token::Arith(op @ (ArithOp::Mul | ArithOp::Div))) =
+            self.tokens.get(self.pos).cloned()
+        {
+            self.pos += 1;
+            expr = Expr::Arith(Box::new(expr), op, Box::new(self.parse_operand()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                if !self.eat(&Token::RParen) {
+                    anyhow::bail!("expect `)` in expression");
+                }
+                Ok(expr)
+            }
+            Some(Token::Str(s)) => Ok(Expr::Literal(s)),
+            Some(Token::Column(name)) => self.column(&name),
+            Some(Token::Word(word)) => {
+                // 列名优先, 其次是数字和bool
+                if let Ok(expr) = self.column(&word) {
+                    Ok(expr)
+                } else if word.parse::<f64>().is_ok()
+                    || word.eq_ignore_ascii_case("true")
+                    || word.eq_ignore_ascii_case("false")
+                {
+                    Ok(Expr::Literal(word))
+                } else {
+                    anyhow::bail!("column not found: {}", word)
+                }
+            }
+            Some(token) => anyhow::bail!("unexpected token in expression: {:?}", token),
+            None => anyhow::bail!("unexpected end of expression"),
+        }
+    }
+
+    fn column(&self, name: &str) -> Result<Expr> {
+        self.header
+            .iter()
+            .position(|h| h == name)
+            .map(Expr::Column)
+            .ok_or_else(|| anyhow::anyhow!("column not found: {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> StringRecord {
+        StringRecord::from(vec!["Name", "Position", "Kit Number"])
+    }
+
+    #[test]
+    fn test_expr_matches() -> Result<()> {
+        let header = header();
+        let buffon = StringRecord::from(vec!["Gianluigi Buffon", "Goalkeeper", "77"]);
+        let perin = StringRecord::from(vec!["Mattia Perin", "Goalkeeper", "1"]);
+        let expr = Expr::parse("Position == 'Goalkeeper' && Kit Number > 10", &header)?;
+        assert!(expr.matches(&buffon));
+        assert!(!expr.matches(&perin));
+
+        let expr = Expr::parse("!(`Kit Number` >= 10) || Name == 'x'", &header)?;
+        assert!(!expr.matches(&buffon));
+        assert!(expr.matches(&perin));
+
+        let expr = Expr::parse("\"Name\" != 'Mattia Perin'", &header)?;
+        assert!(expr.matches(&buffon));
+
+        let expr = Expr::parse("Kit Number * 2 + 1 > 100", &header)?;
+        assert!(expr.matches(&buffon));
+        assert_eq!(Expr::parse("Kit Number - 7", &header)?.value(&buffon), "70");
+        assert_eq!(Expr::parse("Kit Number / 2", &header)?.value(&perin), "0.5");
+        assert_eq!(
+            Expr::parse("Name + ' #' + Kit Number", &header)?.value(&perin),
+            "Mattia Perin #1"
+        );
+        assert_eq!(Expr::parse("Name * 2", &header)?.value(&perin), "");
+
+        // NaN不是数字, 不等于任何数
+        let nan = StringRecord::from(vec!["x", "x", "NaN"]);
+        assert!(!Expr::parse("Kit Number == 5", &header)?.matches(&nan));
+        assert!(Expr::parse("Kit Number != 5", &header)?.matches(&nan));
+
+        assert!(Expr::parse("Age > 10", &header).is_err());
+        assert!(Expr::parse("Name == 'x", &header).is_err());
+        assert!(Expr::parse("(Name == 'x'", &header).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_records() -> Result<()> {
+        let header = header();
+        let mut records = vec![
+            StringRecord::from(vec!["a", "x", "9"]),
+            StringRecord::from(vec!["b", "y", "10"]),
+            StringRecord::from(vec!["c", "x", "1"]),
+        ];
+        let keys = vec![
+            SortKey {
+                column: "Position".to_string(),
+                desc: false,
+            },
+            SortKey {
+                column: "Kit Number".to_string(),
+                desc: true,
+            },
+        ];
+        let keys = resolve_sort_keys(&header, &keys)?;
+        sort_records(&mut records, &keys);
+        let names = records.iter().map(|r| &r[0]).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "c", "b"]);
+
+        // 数字和字符串混在一起: 数字在前, 然后按字符串排序
+        let mut records = ["b", "10", "NaN", "9", "a", "", "1.5"]
+            .iter()
+            .map(|kit| StringRecord::from(vec!["x", "x", kit]))
+            .collect::<Vec<_>>();
+        sort_records(&mut records, &[(2, false)]);
+        let kits = records.iter().map(|r| &r[2]).collect::<Vec<_>>();
+        assert_eq!(kits, vec!["1.5", "9", "10", "", "NaN", "a", "b"]);
+
+        // 日期按字符串排序: ISO格式就是时间顺序, "Apr 18, 1990 (29)" 这种按月份的名字排序
+        let mut records = [
+            "Jan 1, 1980 (39)",
+            "2000-01-02",
+            "Apr 18, 1990 (29)",
+            "1999-12-31",
+        ]
+        .iter()
+        .map(|dob| StringRecord::from(vec!["x", "x", dob]))
+        .collect::<Vec<_>>();
+        sort_records(&mut records, &[(2, true)]);
+        let dobs = records.iter().map(|r| &r[2]).collect::<Vec<_>>();
+        assert_eq!(
+            dobs,
+            vec![
+                "Jan 1, 1980 (39)",
+                "Apr 18, 1990 (29)",
+                "2000-01-02",
+                "1999-12-31"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use super::{serialize_rows, value_to_cell, TOML_ROWS_KEY};
+use crate::{
+    cli::csv_opts::{ArrayFormat, CsvFromOpts, InputFormat, OutputFormat},
+    utils::{get_reader, get_writer},
+};
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::{io::Write, path::Path};
+
+/// convert json/yaml/toml/ndjson documents back to csv,
+/// the header is the union of the keys of all rows
+pub fn process_csv_from(opts: &CsvFromOpts, output: &str) -> Result<()> {
+    let format = match opts.format {
+        Some(format) => format,
+        None => detect_input_format(&opts.input)?,
+    };
+    let mut reader = get_reader(&opts.input)?;
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let rows = parse_rows(&content, format, opts.records_path.as_deref())?
+        .iter()
+        .map(|row| flatten_row(row, opts.arrays, &opts.array_separator))
+        .collect::<Vec<_>>();
+    let content = serialize_rows(&rows, OutputFormat::Csv, opts.delimiter)?;
+    let mut writer = get_writer(output)?;
+    writer.write_all(content.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn detect_input_format(input: &str) -> Result<InputFormat> {
+    Path::new(input)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| anyhow::anyhow!("can not detect input format, please use --format"))?
+        .parse()
+}
+
+/// parse the document into rows, the rows are the array at `records_path` (eg: `data.items`),
+/// or the top level array. toml can't be an array, the rows of a toml document are `[[rows]]`
+/// as written by `rcli csv --format toml`. other documents are a single row
+pub fn parse_rows(
+    content: &str,
+    format: InputFormat,
+    records_path: Option<&str>,
+) -> Result<Vec<Value>> {
+    let doc: Value = match format {
+        InputFormat::Json => serde_json::from_str(content)?,
+        InputFormat::Yaml => serde_yaml::from_str(content)?,
+        InputFormat::Toml => toml::from_str(content)?,
+        InputFormat::Ndjson => {
+            return content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| Ok(serde_json::from_str(line)?))
+                .collect();
+        }
+    };
+    let records_path = match (records_path, format) {
+        (Some(path), _) => Some(path),
+        (None, InputFormat::Toml) if doc.get(TOML_ROWS_KEY).is_some_and(Value::is_array) => {
+            Some(TOML_ROWS_KEY)
+        }
+        _ => None,
+    };
+    let doc = match records_path {
+        Some(path) => path.split('.').try_fold(doc, |doc, key| match doc {
+            Value::Object(mut map) => map
+                .remove(key)
+                .ok_or_else(|| anyhow::anyhow!("--records-path {} is not found", path)),
+            _ => anyhow::bail!("--records-path {} is not found", path),
+        })?,
+        None => doc,
+    };
+    // 没有指定路径的时候不猜测文档的结构, `{"orders": [{...}]}` 是一行
+    let rows = match (doc, records_path) {
+        (Value::Array(rows), _) => rows,
+        (_, Some(path)) => anyhow::bail!("--records-path {} is not an array", path),
+        (other, None) => vec![other],
+    };
+    Ok(rows)
+}
+
+/// flatten nested objects into dotted keys, eg: `{"a": {"b": 1}}` => `{"a.b": 1}`
+pub fn flatten_row(row: &Value, arrays: ArrayFormat, separator: &str) -> Value {
+    let mut out = Map::new();
+    // 不是object的行(eg: 数组里直接是数字)放到 value 列
+    let prefix = if row.is_object() { "" } else { "value" };
+    flatten_value(prefix, row, arrays, separator, &mut out);
+    Value::Object(out)
+}
+
+fn flatten_value(
+    prefix: &str,
+    value: &Value,
+    arrays: ArrayFormat,
+    separator: &str,
+    out: &mut Map<String, Value>,
+) {
+    match (value, arrays) {
+        (Value::Object(obj), _) => {
+            for (key, value) in obj {
+                flatten_value(&join_key(prefix, key), value, arrays, separator, out);
+            }
+        }
+        // 和 `--nested` 的表头一样用 `tags[0]`, 可以转换回数组
+        (Value::Array(arr), ArrayFormat::Index) => {
+            for (idx, value) in arr.iter().enumerate() {
+                let key = format!("{}[{}]", prefix, idx);
+                flatten_value(&key, value, arrays, separator, out);
+            }
+        }
+        (Value::Array(arr), ArrayFormat::Join) => {
+            let joined = arr.iter().map(value_to_cell).collect::<Vec<_>>();
+            out.insert(prefix.to_string(), Value::String(joined.join(separator)));
+        }
+        // 数组在写csv的时候会被渲染成json
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{parse_header_path, values_to_nested_row};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rows() -> Result<()> {
+        let rows = parse_rows(
+            "[[rows]]\nname = \"a\"\n\n[[rows]]\nname = \"b\"\n",
+            InputFormat::Toml,
+            None,
+        )?;
+        assert_eq!(rows, vec![json!({"name": "a"}), json!({"name": "b"})]);
+        let rows = parse_rows("{\"a\":1}\n\n{\"a\":2}\n", InputFormat::Ndjson, None)?;
+        assert_eq!(rows.len(), 2);
+        let rows = parse_rows("- a: 1\n- b: 2\n", InputFormat::Yaml, None)?;
+        assert_eq!(rows, vec![json!({"a": 1}), json!({"b": 2})]);
+        let rows = parse_rows("{\"tags\": [\"a\", \"b\"]}", InputFormat::Json, None)?;
+        assert_eq!(rows, vec![json!({"tags": ["a", "b"]})]);
+
+        // 一条记录里有对象的数组, 不指定--records-path的时候还是一行
+        let doc = "{\"orders\": [{\"id\": 1}, {\"id\": 2}]}";
+        let rows = parse_rows(doc, InputFormat::Json, None)?;
+        assert_eq!(rows, vec![json!({"orders": [{"id": 1}, {"id": 2}]})]);
+        let flat = flatten_row(&rows[0], ArrayFormat::Index, "|");
+        assert_eq!(flat, json!({"orders[0].id": 1, "orders[1].id": 2}));
+        let rows = parse_rows(doc, InputFormat::Json, Some("orders"))?;
+        assert_eq!(rows, vec![json!({"id": 1}), json!({"id": 2})]);
+
+        let doc = "{\"data\": {\"items\": [{\"a\": 1}], \"total\": 1}}";
+        let rows = parse_rows(doc, InputFormat::Json, Some("data.items"))?;
+        assert_eq!(rows, vec![json!({"a": 1})]);
+        assert!(parse_rows(doc, InputFormat::Json, Some("data.total")).is_err());
+        assert!(parse_rows(doc, InputFormat::Json, Some("data.rows")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_row() {
+        let row = json!({"name": "a", "address": {"city": "x", "zip": 1}, "tags": ["t1", "t2"]});
+        assert_eq!(
+            flatten_row(&row, ArrayFormat::Json, "|"),
+            json!({"name": "a", "address.city": "x", "address.zip": 1, "tags": ["t1", "t2"]})
+        );
+        assert_eq!(
+            flatten_row(&row, ArrayFormat::Join, "|")["tags"],
+            json!("t1|t2")
+        );
+        let flat = flatten_row(&row, ArrayFormat::Index, "|");
+        assert_eq!(flat["tags[0]"], json!("t1"));
+        assert_eq!(flat["tags[1]"], json!("t2"));
+
+        // `--arrays index` 的表头可以用 `--nested` 转换回来
+        let row = json!({"name": "a", "tags": [["x", "y"], {"k": 1}]});
+        let flat = flatten_row(&row, ArrayFormat::Index, "|");
+        let flat = flat.as_object().unwrap();
+        let paths = flat
+            .keys()
+            .map(|k| parse_header_path(k))
+            .collect::<Vec<_>>();
+        let nested = values_to_nested_row(&paths, flat.values().cloned()).unwrap();
+        assert_eq!(nested, row);
+        assert_eq!(
+            flatten_row(&json!(1), ArrayFormat::Json, "|"),
+            json!({"value": 1})
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::cli::csv_opts::ColumnType;
+use csv::StringRecord;
+use serde_json::{Number, Value};
+
+/// infer the type of a single cell, empty cell is null
+pub fn infer_value_type(value: &str) -> ColumnType {
+    let value = value.trim();
+    if value.is_empty() {
+        ColumnType::Null
+    } else if parse_integer(value).is_some() {
+        ColumnType::Integer
+    } else if parse_float(value).is_some() {
+        ColumnType::Float
+    } else if parse_bool(value).is_some() {
+        ColumnType::Boolean
+    } else {
+        ColumnType::String
+    }
+}
+
+/// merge two types of the same column into the narrowest type that fits both
+pub fn merge_column_type(a: ColumnType, b: ColumnType) -> ColumnType {
+    match (a, b) {
+        (ColumnType::Null, t) | (t, ColumnType::Null) => t,
+        (a, b) if a == b => a,
+        (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+            ColumnType::Float
+        }
+        _ => ColumnType::String,
+    }
+}
+
+/// scan all records and infer the type of each column
+pub fn infer_column_types(records: &[StringRecord], width: usize) -> Vec<ColumnType> {
+    let mut types = vec![ColumnType::Null; width];
+    for record in records {
+        update_column_types(&mut types, record);
+    }
+    types
+}
+
+/// merge the types of one record into the column types, used when streaming
+pub fn update_column_types(types: &mut [ColumnType], record: &StringRecord) {
+    for (ty, value) in types.iter_mut().zip(record.iter()) {
+        *ty = merge_column_type(*ty, infer_value_type(value));
+    }
+}
+
+/// apply the `--type COLUMN=TYPE` overrides on the inferred types
+pub fn apply_type_overrides(
+    header: &StringRecord,
+    types: &mut [ColumnType],
+    overrides: &[(String, ColumnType)],
+) -> anyhow::Result<()> {
+    for (column, ty) in overrides {
+        let idx = header
+            .iter()
+            .position(|h| h == column)
+            .ok_or_else(|| anyhow::anyhow!("column not found: {}", column))?;
+        types[idx] = *ty;
+    }
+    Ok(())
+}
+
+/// convert a cell to json value with the given type,
+/// if the cell does not fit the type, keep it as string
+pub fn typed_value(value: &str, ty: ColumnType) -> Value {
+    if ty == ColumnType::String {
+        return Value::String(value.to_string());
+    }
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Value::Null;
+    }
+    let typed = match ty {
+        ColumnType::Integer => parse_integer(trimmed).map(Value::from),
+        ColumnType::Float => parse_float(trimmed)
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+        ColumnType::Boolean => parse_bool(trimmed).map(Value::Bool),
+        ColumnType::Null | ColumnType::String => None,
+    };
+    typed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// the number of a cell by the inference rules, "NaN", "inf" and "007" are not numbers
+pub fn parse_number(value: &str) -> Option<f64> {
+    parse_float(value.trim())
+}
+
+// "007" 这种带前导0的一般是编号, 转成数字会丢信息
+fn has_leading_zero(value: &str) -> bool {
+    let digits = value.trim_start_matches(['+', '-']);
+    let int_part = digits.split(['.', 'e', 'E']).next().unwrap_or_default();
+    int_part.len() > 1 && int_part.starts_with('0')
+}
+
+fn parse_integer(value: &str) -> Option<i64> {
+    if has_leading_zero(value) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn parse_float(value: &str) -> Option<f64> {
+    // f64::from_str 也能解析 "inf" "NaN", 这里只接受普通的数字
+    if has_leading_zero(value) || !value.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // 超出i64的整数一般是很长的编号, 转成浮点数会丢失精度, 当作字符串
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    if digits.bytes().all(|b| b.is_ascii_digit()) && value.parse::<i64>().is_err() {
+        return None;
+    }
+    value.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_value_type() {
+        assert_eq!(infer_value_type(""), ColumnType::Null);
+        assert_eq!(infer_value_type("37"), ColumnType::Integer);
+        assert_eq!(infer_value_type("-1"), ColumnType::Integer);
+        assert_eq!(infer_value_type("3.14"), ColumnType::Float);
+        assert_eq!(infer_value_type("1e3"), ColumnType::Float);
+        assert_eq!(infer_value_type("TRUE"), ColumnType::Boolean);
+        assert_eq!(infer_value_type("007"), ColumnType::String);
+        assert_eq!(infer_value_type("NaN"), ColumnType::String);
+        assert_eq!(infer_value_type("Goalkeeper"), ColumnType::String);
+    }
+
+    #[test]
+    fn test_infer_column_types() {
+        let records = vec![
+            StringRecord::from(vec!["1", "1", "", "true", "a"]),
+            StringRecord::from(vec!["2", "1.5", "", "false", "1"]),
+            StringRecord::from(vec!["", "2", "", "", "b"]),
+        ];
+        let types = infer_column_types(&records, 5);
+        assert_eq!(
+            types,
+            vec![
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Null,
+                ColumnType::Boolean,
+                ColumnType::String
+            ]
+        );
+    }
+
+    #[test]
+    fn test_typed_value() {
+        assert_eq!(typed_value("1", ColumnType::Integer), json!(1));
+        assert_eq!(typed_value("", ColumnType::Integer), Value::Null);
+        assert_eq!(typed_value("1.5", ColumnType::Float), json!(1.5));
+        assert_eq!(typed_value("x", ColumnType::Float), json!("x"));
+        assert_eq!(typed_value("True", ColumnType::Boolean), json!(true));
+        assert_eq!(typed_value("", ColumnType::String), json!(""));
+        // 超出i64的整数不会变成浮点数
+        let id = "12345678901234567890";
+        assert_eq!(infer_value_type(id), ColumnType::String);
+        assert_eq!(infer_value_type("-9223372036854775809"), ColumnType::String);
+        assert_eq!(typed_value(id, ColumnType::Float), json!(id));
+        assert_eq!(typed_value("2", ColumnType::Float), json!(2.0));
+    }
+
+    #[test]
+    fn test_apply_type_overrides() {
+        let header = StringRecord::from(vec!["Name", "Kit Number"]);
+        let mut types = vec![ColumnType::String, ColumnType::Integer];
+        let overrides = vec![("Kit Number".to_string(), ColumnType::String)];
+        apply_type_overrides(&header, &mut types, &overrides).unwrap();
+        assert_eq!(types, vec![ColumnType::String, ColumnType::String]);
+
+        let overrides = vec![("Age".to_string(), ColumnType::Integer)];
+        assert!(apply_type_overrides(&header, &mut types, &overrides).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use super::{build_csv_reader, infer_value_type, merge_column_type, ColumnSchema, CsvSchema};
+use crate::cli::csv_opts::{ColumnType, CsvInferSchemaOpts, SchemaFormat};
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{json, Map, Value};
+
+pub fn process_csv_infer_schema(opts: &CsvInferSchemaOpts) -> Result<String> {
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    let records = reader.records().take(opts.sample.unwrap_or(usize::MAX));
+    let schema = infer_schema(&header, records, opts.max_enum)?;
+    let output = match opts.format {
+        SchemaFormat::Yaml => serde_yaml::to_string(&schema)?,
+        SchemaFormat::JsonSchema => serde_json::to_string_pretty(&to_json_schema(&schema))? + "\n",
+    };
+    Ok(output)
+}
+
+/// scan the records and infer the type, nullability, enum values and length bounds of each column
+pub fn infer_schema(
+    header: &StringRecord,
+    records: impl Iterator<Item = csv::Result<StringRecord>>,
+    max_enum: usize,
+) -> Result<CsvSchema> {
+    let mut columns = header
+        .iter()
+        .map(|_| ColumnSummary::default())
+        .collect::<Vec<_>>();
+    for record in records {
+        let record = record?;
+        for (idx, column) in columns.iter_mut().enumerate() {
+            column.add(record.get(idx).unwrap_or_default(), max_enum);
+        }
+    }
+    let columns = header
+        .iter()
+        .zip(columns)
+        .map(|(name, column)| column.finish(name, max_enum))
+        .collect();
+    Ok(CsvSchema {
+        columns,
+        strict: false,
+    })
+}
+
+/// convert the column spec to a JSON Schema describing one row of `rcli csv --infer`
+pub fn to_json_schema(schema: &CsvSchema) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for column in &schema.columns {
+        let ty = column.ty.unwrap_or(ColumnType::String);
+        let mut property = Map::new();
+        if column.required || ty == ColumnType::Null {
+            property.insert("type".to_string(), json!(json_schema_type(ty)));
+            required.push(Value::String(column.name.clone()));
+        } else {
+            property.insert("type".to_string(), json!([json_schema_type(ty), "null"]));
+        }
+        if let Some(values) = &column.values {
+            let mut values = values.clone();
+            if !column.required {
+                values.push(Value::Null);
+            }
+            property.insert("enum".to_string(), Value::Array(values));
+        }
+        if let Some(min) = column.min_length {
+            property.insert("minLength".to_string(), json!(min));
+        }
+        if let Some(max) = column.max_length {
+            property.insert("maxLength".to_string(), json!(max));
+        }
+        let property = if ty == ColumnType::String && !column.required {
+            // `--infer` 原样输出字符串列的空值, eg: "" 或者 "  ", 不是null
+            json!({"anyOf": [property, {"type": "string", "pattern": "^\\s*$"}]})
+        } else {
+            Value::Object(property)
+        };
+        properties.insert(column.name.clone(), property);
+    }
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn json_schema_type(ty: ColumnType) -> &'static str {
+    match ty {
+        ColumnType::Null => "null",
+        ColumnType::Boolean => "boolean",
+        ColumnType::Integer => "integer",
+        ColumnType::Float => "number",
+        ColumnType::String => "string",
+    }
+}
+
+#[derive(Default)]
+struct ColumnSummary {
+    ty: Option<ColumnType>,
+    count: u64,
+    nulls: u64,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    /// 按出现顺序记录不同的值, 超过max_enum之后就不再是enum了
+    values: Option<Vec<String>>,
+}
+
+impl ColumnSummary {
+    fn add(&mut self, value: &str, max_enum: usize) {
+        let ty = infer_value_type(value);
+        self.ty = Some(self.ty.map_or(ty, |t| merge_column_type(t, ty)));
+        if ty == ColumnType::Null {
+            self.nulls += 1;
+            return;
+        }
+        self.count += 1;
+        let len = value.chars().count();
+        self.min_length = Some(self.min_length.map_or(len, |m| m.min(len)));
+        self.max_length = Some(self.max_length.map_or(len, |m| m.max(len)));
+        if self.count == 1 {
+            self.values = Some(vec![]);
+        }
+        if let Some(values) = &mut self.values {
+            if !values.iter().any(|v| v == value) {
+                if values.len() < max_enum {
+                    values.push(value.to_string());
+                } else {
+                    self.values = None;
+                }
+            }
+        }
+    }
+
+    fn finish(self, name: &str, max_enum: usize) -> ColumnSchema {
+        let ty = self.ty.unwrap_or(ColumnType::Null);
+        let is_string = ty == ColumnType::String;
+        // 每个值都不一样的列(eg: 名字)不算enum
+        let values = self
+            .values
+            .filter(|values| is_string && max_enum > 0 && (values.len() as u64) < self.count)
+            .map(|values| values.into_iter().map(Value::String).collect());
+        ColumnSchema {
+            name: name.to_string(),
+            ty: Some(ty),
+            required: self.count > 0 && self.nulls == 0,
+            values,
+            min_length: self.min_length.filter(|_| is_string),
+            max_length: self.max_length.filter(|_| is_string),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::csv_opts::{CsvConvertOpts, CsvReaderOpts},
+        process::{process_csv, validate_records, value_to_cell},
+    };
+
+    #[test]
+    fn test_infer_schema() -> Result<()> {
+        let opts = CsvReaderOpts::default();
+        let mut reader = build_csv_reader("assets/juventus.csv", &opts)?;
+        let header = reader.headers()?.clone();
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let schema = infer_schema(&header, records.iter().cloned().map(Ok), 10)?;
+
+        let position = &schema.columns[1];
+        assert_eq!(position.ty, Some(ColumnType::String));
+        assert!(position.required);
+        let values = position
+            .values
+            .as_ref()
+            .expect("position should be an enum");
+        assert!(values.iter().any(|v| value_to_cell(v) == "Goalkeeper"));
+        assert!(position.min_length.is_some());
+        // 名字每个都不一样, 不是enum
+        assert!(schema.columns[0].values.is_none());
+        assert_eq!(schema.columns[4].ty, Some(ColumnType::Integer));
+        assert!(schema.columns[4].min_length.is_none());
+
+        // 推断出来的schema可以直接用于validate, 原文件没有违规
+        let yaml = serde_yaml::to_string(&schema)?;
+        let schema: CsvSchema = serde_yaml::from_str(&yaml)?;
+        let (violations, _) = validate_records(&schema, &header, records.into_iter().map(Ok))?;
+        assert!(violations.is_empty());
+
+        let json_schema = to_json_schema(&schema);
+        assert_eq!(json_schema["properties"]["Kit Number"]["type"], "integer");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_schema_matches_infer_output() -> Result<()> {
+        let input = std::env::temp_dir().join("rcli_test_json_schema.csv");
+        std::fs::write(
+            &input,
+            "name,pos,kit\nBuffon,gk,1\nPerin,,\nDanilo,df,6\nBonucci,  ,19\n",
+        )?;
+        let input = input.to_str().unwrap();
+        let output = std::env::temp_dir().join("rcli_test_json_schema.json");
+        let output = output.to_str().unwrap();
+        let opts = <CsvConvertOpts as clap::Parser>::parse_from(["csv", "-i", input, "--infer"]);
+        process_csv(&opts, output)?;
+        let rows: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(output)?)?;
+        assert_eq!(rows[1]["pos"], "");
+        assert_eq!(rows[1]["kit"], Value::Null);
+
+        let mut reader = build_csv_reader(input, &CsvReaderOpts::default())?;
+        let header = reader.headers()?.clone();
+        let schema = to_json_schema(&infer_schema(&header, reader.records(), 10)?);
+        for row in &rows {
+            for (name, value) in row.as_object().unwrap() {
+                let property = &schema["properties"][name];
+                assert!(
+                    matches_schema(value, property),
+                    "{}: {} {}",
+                    name,
+                    value,
+                    property
+                );
+            }
+        }
+        // 非空的值还是要满足长度限制
+        assert!(!matches_schema(
+            &json!("keeper"),
+            &schema["properties"]["pos"]
+        ));
+        Ok(())
+    }
+
+    /// the keywords used by `to_json_schema`
+    fn matches_schema(value: &Value, schema: &Value) -> bool {
+        if let Some(schemas) = schema["anyOf"].as_array() {
+            return schemas.iter().any(|schema| matches_schema(value, schema));
+        }
+        let type_matches = |ty: &Value| match ty.as_str().unwrap_or_default() {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "string" => value.is_string(),
+            _ => false,
+        };
+        let type_ok = match &schema["type"] {
+            Value::Array(types) => types.iter().any(type_matches),
+            ty => type_matches(ty),
+        };
+        let enum_ok = schema["enum"]
+            .as_array()
+            .is_none_or(|values| values.contains(value));
+        let Some(s) = value.as_str() else {
+            return type_ok && enum_ok;
+        };
+        let len = s.chars().count() as u64;
+        let pattern = schema["pattern"].as_str().unwrap_or_default();
+        type_ok
+            && enum_ok
+            && schema["minLength"].as_u64().is_none_or(|min| len >= min)
+            && schema["maxLength"].as_u64().is_none_or(|max| len <= max)
+            && regex::Regex::new(pattern).unwrap().is_match(s)
+    }
+}
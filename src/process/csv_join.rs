@@ -0,0 +1,332 @@
+use super::{build_csv_reader, columnar_schema, new_row_writer, ColumnarWriter, RowWriter};
+use crate::{
+    cli::csv_opts::{ColumnType, CsvJoinOpts, JoinType, OutputFormat},
+    utils::get_writer,
+};
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+};
+
+pub fn process_csv_join(opts: &CsvJoinOpts) -> Result<()> {
+    let left_on = opts.left_on.as_ref().or(opts.on.as_ref());
+    let right_on = opts.right_on.as_ref().or(opts.on.as_ref());
+    let (Some(left_on), Some(right_on)) = (left_on, right_on) else {
+        anyhow::bail!("join key is required, use --on or --left-on/--right-on");
+    };
+    let mut left = build_csv_reader(&opts.left, &opts.reader)?;
+    let mut right = build_csv_reader(&opts.right, &opts.reader)?;
+    let joiner = Joiner::new(left.headers()?, left_on, right.headers()?, right_on)?;
+
+    let mut writer: Box<dyn RowWriter> = match opts.format {
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            // 关联的结果都是字符串, 直接给出schema, 不用缓存所有的行来推断类型
+            let columns = joiner
+                .columns()
+                .into_iter()
+                .map(|name| (name, Some(ColumnType::String)))
+                .collect::<Vec<_>>();
+            let writer = ColumnarWriter::new(opts.format, &opts.writer, get_writer(&opts.output)?);
+            Box::new(writer.with_schema(columnar_schema(&columns))?)
+        }
+        _ => new_row_writer(
+            opts.format,
+            opts.out_delimiter,
+            &opts.writer,
+            get_writer(&opts.output)?,
+        ),
+    };
+    let mut count = 0;
+    let mut emit = |row: Value| {
+        count += 1;
+        writer.write_row(&row)
+    };
+    // 小的文件放到hash表里, 大的文件流式读取, 标准输入只能流式读取
+    let size = |path: &str| fs::metadata(path).map_or(u64::MAX, |m| m.len());
+    if size(&opts.left) <= size(&opts.right) {
+        joiner.join(left.records(), right.records(), true, opts.how, &mut emit)?;
+    } else {
+        joiner.join(right.records(), left.records(), false, opts.how, &mut emit)?;
+    }
+    writer.finish()?;
+    eprintln!("{} rows joined", count);
+    Ok(())
+}
+
+/// hash join of two csv files, the output row has all left columns followed by the right columns
+pub struct Joiner {
+    left_header: StringRecord,
+    left_key: usize,
+    right_key: usize,
+    /// output names of the right columns, none for the right key
+    right_names: Vec<Option<String>>,
+}
+
+impl Joiner {
+    pub fn new(
+        left_header: StringRecord,
+        left_on: &str,
+        right_header: StringRecord,
+        right_on: &str,
+    ) -> Result<Self> {
+        let position = |header: &StringRecord, key: &str| {
+            header
+                .iter()
+                .position(|h| h == key)
+                .ok_or_else(|| anyhow::anyhow!("column not found: {}", key))
+        };
+        let left_key = position(&left_header, left_on)?;
+        let right_key = position(&right_header, right_on)?;
+        // 右边的关联列和左边的合并成一列, 重名的列加上_right后缀, 后缀也重名的时候再加上序号
+        let taken = left_header
+            .iter()
+            .chain(right_header.iter())
+            .collect::<HashSet<_>>();
+        let mut used = left_header.iter().map(String::from).collect::<HashSet<_>>();
+        let right_names = right_header
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| {
+                if idx == right_key {
+                    return None;
+                }
+                let name = if used.contains(name) {
+                    let suffixed = format!("{}_right", name);
+                    std::iter::once(suffixed.clone())
+                        .chain((2..).map(|n| format!("{}_{}", suffixed, n)))
+                        .find(|candidate| {
+                            !taken.contains(candidate.as_str()) && !used.contains(candidate)
+                        })
+                        .unwrap_or_default()
+                } else {
+                    name.to_string()
+                };
+                used.insert(name.clone());
+                Some(name)
+            })
+            .collect();
+        Ok(Self {
+            left_header,
+            left_key,
+            right_key,
+            right_names,
+        })
+    }
+
+    /// load the build side into a hash table and stream the probe side, the matches are
+    /// emitted as the probe records are read, so only the build side is kept in memory.
+    /// the output follows the order of the probe side, the build rows without a match
+    /// (left join when the left side is built, full join) are the last
+    pub fn join(
+        &self,
+        build: impl Iterator<Item = csv::Result<StringRecord>>,
+        probe: impl Iterator<Item = csv::Result<StringRecord>>,
+        build_left: bool,
+        how: JoinType,
+        mut emit: impl FnMut(Value) -> Result<()>,
+    ) -> Result<()> {
+        let (build_key, probe_key) = if build_left {
+            (self.left_key, self.right_key)
+        } else {
+            (self.right_key, self.left_key)
+        };
+        let build = build.collect::<Result<Vec<_>, _>>()?;
+        let mut table: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, record) in build.iter().enumerate() {
+            let key = record.get(build_key).unwrap_or_default();
+            table.entry(key).or_default().push(idx);
+        }
+        let keep_left = matches!(how, JoinType::Left | JoinType::Full);
+        let keep_right = how == JoinType::Full;
+        let (keep_build, keep_probe) = if build_left {
+            (keep_left, keep_right)
+        } else {
+            (keep_right, keep_left)
+        };
+        // 左右两边的行按输出的顺序排好
+        let row = |build: Option<&StringRecord>, probe: Option<&StringRecord>| {
+            if build_left {
+                self.row(build, probe)
+            } else {
+                self.row(probe, build)
+            }
+        };
+
+        let mut matched = vec![false; build.len()];
+        for record in probe {
+            let record = record?;
+            let key = record.get(probe_key).unwrap_or_default();
+            match table.get(key) {
+                Some(indexes) => {
+                    for &idx in indexes {
+                        matched[idx] = true;
+                        emit(row(Some(&build[idx]), Some(&record)))?;
+                    }
+                }
+                None if keep_probe => emit(row(None, Some(&record)))?,
+                None => {}
+            }
+        }
+        if keep_build {
+            for (record, _) in build.iter().zip(matched).filter(|(_, m)| !m) {
+                emit(row(Some(record), None))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the names of the output columns, in the order of `row`
+    pub fn columns(&self) -> Vec<String> {
+        let left = self.left_header.iter().map(String::from);
+        left.chain(self.right_names.iter().flatten().cloned())
+            .collect()
+    }
+
+    /// zip the matched records into one row, missing side is filled with null
+    pub fn row(&self, left: Option<&StringRecord>, right: Option<&StringRecord>) -> Value {
+        let mut row = Map::new();
+        for (idx, name) in self.left_header.iter().enumerate() {
+            let value = match (left, right) {
+                (Some(left), _) => left.get(idx).map(Value::from),
+                // 左边没有匹配的时候关联列取右边的值
+                (None, Some(right)) if idx == self.left_key => {
+                    right.get(self.right_key).map(Value::from)
+                }
+                _ => None,
+            };
+            row.insert(name.to_string(), value.unwrap_or(Value::Null));
+        }
+        for (idx, name) in self.right_names.iter().enumerate() {
+            if let Some(name) = name {
+                let value = right.and_then(|r| r.get(idx)).map(Value::from);
+                row.insert(name.clone(), value.unwrap_or(Value::Null));
+            }
+        }
+        Value::Object(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn join(how: JoinType, build_left: bool) -> Result<Vec<Value>> {
+        let mut left = csv::Reader::from_reader("id,name\n1,a\n2,b\n3,c\n".as_bytes());
+        let mut right =
+            csv::Reader::from_reader("uid,name,score\n1,x,10\n1,y,11\n4,z,12\n".as_bytes());
+        let joiner = Joiner::new(
+            left.headers()?.clone(),
+            "id",
+            right.headers()?.clone(),
+            "uid",
+        )?;
+        let mut rows = vec![];
+        let emit = |row| {
+            rows.push(row);
+            Ok(())
+        };
+        if build_left {
+            joiner.join(left.records(), right.records(), true, how, emit)?;
+        } else {
+            joiner.join(right.records(), left.records(), false, how, emit)?;
+        }
+        Ok(rows)
+    }
+
+    /// sort the rows so that the results of both build sides can be compared
+    fn sorted(mut rows: Vec<Value>) -> Vec<Value> {
+        rows.sort_by_key(|row| row.to_string());
+        rows
+    }
+
+    #[test]
+    fn test_join() -> Result<()> {
+        let rows = join(JoinType::Inner, true)?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"id": "1", "name": "a", "name_right": "x", "score": "10"}),
+                json!({"id": "1", "name": "a", "name_right": "y", "score": "11"}),
+            ]
+        );
+        assert_eq!(join(JoinType::Left, true)?.len(), 4);
+        // 按流式读取的右边的顺序输出, 左边没有匹配的行在最后
+        let ids = join(JoinType::Full, true)?
+            .iter()
+            .map(|row| (row["id"].clone(), row["name_right"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                (json!("1"), json!("x")),
+                (json!("1"), json!("y")),
+                (json!("4"), json!("z")),
+                (json!("2"), json!(null)),
+                (json!("3"), json!(null)),
+            ]
+        );
+
+        // 不管哪边放到hash表里, 结果都一样
+        for how in [JoinType::Inner, JoinType::Left, JoinType::Full] {
+            assert_eq!(sorted(join(how, true)?), sorted(join(how, false)?));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_streams_probe_side() -> Result<()> {
+        let mut left = csv::Reader::from_reader("id\n1\n2\n".as_bytes());
+        let mut right = csv::Reader::from_reader("id\n2\n1\n3\n".as_bytes());
+        let joiner = Joiner::new(
+            left.headers()?.clone(),
+            "id",
+            right.headers()?.clone(),
+            "id",
+        )?;
+        // 每输出一行时记下已经读了多少行右边的记录
+        let read = std::cell::Cell::new(0);
+        let probe = right.records().inspect(|_| read.set(read.get() + 1));
+        let mut emitted = vec![];
+        joiner.join(left.records(), probe, true, JoinType::Full, |row| {
+            emitted.push((row["id"].clone(), read.get()));
+            Ok(())
+        })?;
+        assert_eq!(
+            emitted,
+            vec![(json!("2"), 1), (json!("1"), 2), (json!("3"), 3)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_column_names() -> Result<()> {
+        let left = StringRecord::from(vec!["id", "name", "name_right"]);
+        let right = StringRecord::from(vec!["id", "name", "name_right", "x", "x"]);
+        let joiner = Joiner::new(left, "id", right.clone(), "id")?;
+        let row = joiner.row(
+            None,
+            Some(&StringRecord::from(vec!["1", "a", "b", "c", "d"])),
+        );
+        let names = row.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "id",
+                "name",
+                "name_right",
+                "name_right_2",
+                "name_right_right",
+                "x",
+                "x_right"
+            ]
+        );
+        assert_eq!(row["name_right_2"], "a");
+        assert_eq!(joiner.columns().iter().collect::<Vec<_>>(), names);
+        assert_eq!(row["x_right"], "d");
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use super::{infer_value_type, merge_column_type, typed_value, Expr};
+use crate::cli::csv_opts::ColumnType;
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+
+/// a checked-in mapping from a partner's layout to ours, eg:
+///
+/// ```yaml
+/// columns:
+///   - name: kit_number
+///     from: Kit Number
+///     type: integer
+///   - name: birthday
+///     from: DOB
+///     date: "%b %d, %Y"
+///   - name: label
+///     expr: "Name + ' #' + Kit Number"
+///   - name: country
+///     from: Nationality
+///     default: unknown
+/// keep_unmapped: true
+/// drop: [Position]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvMapping {
+    /// 输出的列, 按这个顺序输出
+    #[serde(default)]
+    pub columns: Vec<ColumnMapping>,
+    /// 没有映射的列按原来的顺序放在后面, 默认丢弃
+    #[serde(default)]
+    pub keep_unmapped: bool,
+    /// keep_unmapped的时候也不输出的列
+    #[serde(default)]
+    pub drop: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnMapping {
+    /// 输出的列名
+    pub name: String,
+    /// 原来的列名, 默认和name一样
+    pub from: Option<String>,
+    /// 派生列的表达式, 语法和 `--where` 一样, 列名是原来的列名
+    pub expr: Option<String>,
+    /// 默认是原来的列的类型, 派生列根据每个值推断
+    #[serde(rename = "type")]
+    pub ty: Option<ColumnType>,
+    /// 日期的格式, eg: "%b %d, %Y", 输出为ISO-8601, 后面多余的内容会被忽略
+    pub date: Option<String>,
+    /// 值为空的时候输出的值
+    pub default: Option<Value>,
+}
+
+impl CsvMapping {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).with_context(|| format!("invalid mapping file: {}", path))
+    }
+}
+
+/// the mapping resolved against the header of the input
+#[derive(Debug)]
+pub struct Mapper {
+    header: StringRecord,
+    columns: Vec<MappedColumn>,
+}
+
+#[derive(Debug)]
+struct MappedColumn {
+    source: Source,
+    ty: Option<ColumnType>,
+    date: Option<String>,
+    default: Option<Value>,
+}
+
+#[derive(Debug)]
+enum Source {
+    Column(usize),
+    Expr(Expr),
+}
+
+impl Mapper {
+    /// `types` are the types of the input columns, used by the columns without `type`
+    pub fn new(mapping: &CsvMapping, header: &StringRecord, types: &[ColumnType]) -> Result<Self> {
+        let position = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow::anyhow!("column not found in mapping: {}", name))
+        };
+        let mut names = vec![];
+        let mut columns = vec![];
+        let mut mapped = vec![false; header.len()];
+        for column in &mapping.columns {
+            let (source, ty) = match (&column.from, &column.expr) {
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("column {} has both `from` and `expr`", column.name)
+                }
+                (_, Some(expr)) => (Source::Expr(Expr::parse(expr, header)?), column.ty),
+                (from, None) => {
+                    let idx = position(from.as_deref().unwrap_or(&column.name))?;
+                    mapped[idx] = true;
+                    (Source::Column(idx), column.ty.or(Some(types[idx])))
+                }
+            };
+            if column.date.is_some() && column.ty.is_some() {
+                anyhow::bail!("column {} has both `date` and `type`", column.name);
+            }
+            names.push(column.name.as_str());
+            columns.push(MappedColumn {
+                source,
+                ty,
+                date: column.date.clone(),
+                default: column.default.clone(),
+            });
+        }
+        for name in &mapping.drop {
+            mapped[position(name)?] = true;
+        }
+        if mapping.keep_unmapped {
+            for (idx, name) in header.iter().enumerate().filter(|(idx, _)| !mapped[*idx]) {
+                names.push(name);
+                columns.push(MappedColumn::plain(idx, types[idx]));
+            }
+        }
+        Ok(Self {
+            header: names.into_iter().collect(),
+            columns,
+        })
+    }
+
+    /// output the columns at `indexes` as they are, used when there's no mapping file
+    pub fn select(header: &StringRecord, indexes: &[usize], types: &[ColumnType]) -> Self {
+        Self {
+            header: indexes.iter().map(|&idx| &header[idx]).collect(),
+            columns: indexes
+                .iter()
+                .map(|&idx| MappedColumn::plain(idx, types[idx]))
+                .collect(),
+        }
+    }
+
+    /// the output column names
+    pub fn header(&self) -> &StringRecord {
+        &self.header
+    }
+
+    /// the type of each output column for typed outputs (parquet, arrow), `None` when the values
+    /// can have different types, eg: derived columns. `inferred` are the types of the input columns
+    /// from the whole file, a declared type that doesn't fit the values is an error
+    pub fn column_types(&self, inferred: &[ColumnType]) -> Result<Vec<Option<ColumnType>>> {
+        self.header
+            .iter()
+            .zip(&self.columns)
+            .map(|(name, column)| {
+                if column.date.is_some() {
+                    return Ok(Some(ColumnType::String));
+                }
+                let ty = match (&column.source, column.ty) {
+                    (_, Some(ColumnType::Null | ColumnType::String)) => ColumnType::String,
+                    (Source::Column(idx), Some(ty)) => {
+                        let inferred = inferred[*idx];
+                        if merge_column_type(inferred, ty) != ty {
+                            anyhow::bail!(
+                                "column {} is {} but has {} values, the column type must fit all the values",
+                                name,
+                                ty,
+                                inferred
+                            );
+                        }
+                        ty
+                    }
+                    // 派生列的值要计算出来才知道类型
+                    (Source::Expr(_), _) | (_, None) => return Ok(None),
+                };
+                let default = column.default.as_ref().map(|default| match default {
+                    Value::Null => ty,
+                    Value::Bool(_) => ColumnType::Boolean,
+                    Value::Number(n) if n.is_i64() => ColumnType::Integer,
+                    Value::Number(_) => ColumnType::Float,
+                    _ => ColumnType::String,
+                });
+                match default {
+                    Some(default) if merge_column_type(default, ty) != ty => Ok(None),
+                    _ => Ok(Some(ty)),
+                }
+            })
+            .collect()
+    }
+
+    /// the typed values of the output columns
+    pub fn map(&self, record: &StringRecord) -> Result<Vec<Value>> {
+        self.columns
+            .iter()
+            .map(|column| column.value(record))
+            .collect()
+    }
+}
+
+impl MappedColumn {
+    fn plain(idx: usize, ty: ColumnType) -> Self {
+        Self {
+            source: Source::Column(idx),
+            ty: Some(ty),
+            date: None,
+            default: None,
+        }
+    }
+
+    fn value(&self, record: &StringRecord) -> Result<Value> {
+        let expr_value;
+        let cell = match &self.source {
+            Source::Column(idx) => record.get(*idx).unwrap_or_default(),
+            Source::Expr(expr) => {
+                expr_value = expr.value(record);
+                &expr_value
+            }
+        };
+        if let (Some(default), true) = (&self.default, cell.trim().is_empty()) {
+            return Ok(default.clone());
+        }
+        if let Some(format) = &self.date {
+            return parse_date(cell, format);
+        }
+        let ty = self.ty.unwrap_or_else(|| infer_value_type(cell));
+        Ok(typed_value(cell, ty))
+    }
+}
+
+/// parse the date (or date time) into ISO-8601, the trailing text is ignored,
+/// eg: "Apr 18, 1990 (29)" with "%b %d, %Y" is "1990-04-18"
+fn parse_date(value: &str, format: &str) -> Result<Value> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Value::Null);
+    }
+    // 格式里没有时间的时候解析NaiveDateTime会失败, 再当作日期解析
+    let iso = match NaiveDateTime::parse_and_remainder(value, format) {
+        Ok((datetime, _)) => datetime.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        Err(_) => NaiveDate::parse_and_remainder(value, format)
+            .map(|(date, _)| date.format("%Y-%m-%d").to_string())
+            .with_context(|| format!("can't parse date {:?} with format {:?}", value, format))?,
+    };
+    Ok(Value::String(iso))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_mapper() -> Result<()> {
+        let mapping: CsvMapping = serde_yaml::from_str(
+            r#"
+columns:
+  - name: kit_number
+    from: Kit Number
+  - name: birthday
+    from: DOB
+    date: "%b %d, %Y"
+  - name: label
+    expr: "Name + ' #' + Kit Number"
+  - name: double
+    expr: "Kit Number * 2"
+  - name: country
+    from: Nationality
+    default: unknown
+keep_unmapped: true
+drop: [Position]
+"#,
+        )?;
+        let header =
+            StringRecord::from(vec!["Name", "Position", "DOB", "Nationality", "Kit Number"]);
+        let types = [
+            ColumnType::String,
+            ColumnType::String,
+            ColumnType::String,
+            ColumnType::String,
+            ColumnType::Integer,
+        ];
+        let mapper = Mapper::new(&mapping, &header, &types)?;
+        assert_eq!(
+            mapper.header(),
+            &StringRecord::from(vec![
+                "kit_number",
+                "birthday",
+                "label",
+                "double",
+                "country",
+                "Name"
+            ])
+        );
+        let record = StringRecord::from(vec![
+            "Mattia Perin",
+            "Goalkeeper",
+            "Nov 10, 1992 (26)",
+            "",
+            "37",
+        ]);
+        assert_eq!(
+            mapper.map(&record)?,
+            vec![
+                json!(37),
+                json!("1992-11-10"),
+                json!("Mattia Perin #37"),
+                json!(74),
+                json!("unknown"),
+                json!("Mattia Perin"),
+            ]
+        );
+
+        let record = StringRecord::from(vec!["x", "y", "sometime", "", "1"]);
+        assert!(mapper.map(&record).is_err());
+        assert_eq!(
+            mapper.column_types(&types)?,
+            vec![
+                Some(ColumnType::Integer),
+                Some(ColumnType::String),
+                None,
+                None,
+                Some(ColumnType::String),
+                Some(ColumnType::String),
+            ]
+        );
+        // Kit Number 有不是整数的值
+        let mut floats = types;
+        floats[4] = ColumnType::Float;
+        assert!(Mapper::new(&mapping, &header, &types)?
+            .column_types(&floats)
+            .is_err());
+
+        let mapping = CsvMapping {
+            columns: vec![ColumnMapping {
+                name: "Age".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(Mapper::new(&mapping, &header, &types).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use super::typed_value;
+use crate::cli::csv_opts::ColumnType;
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+/// a segment of a dotted header, eg: `address.tags[0]` => [Key(address), Key(tags), Index(0)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// parse a header like `address.city` or `tags[0]` into path segments
+pub fn parse_header_path(header: &str) -> Vec<PathSegment> {
+    let mut path = vec![];
+    for part in header.split('.') {
+        let (key, indexes) = split_indexes(part);
+        path.push(PathSegment::Key(key.to_string()));
+        path.extend(indexes.into_iter().map(PathSegment::Index));
+    }
+    path
+}
+
+// "tags[0][1]" => ("tags", [0, 1]), 不合法的下标(eg: "a[x]")整体当作key
+fn split_indexes(part: &str) -> (&str, Vec<usize>) {
+    let mut key = part;
+    let mut indexes = vec![];
+    while let Some(rest) = key.strip_suffix(']') {
+        let Some((head, idx)) = rest.rsplit_once('[') else {
+            break;
+        };
+        let Ok(idx) = idx.parse() else {
+            break;
+        };
+        indexes.push(idx);
+        key = head;
+    }
+    if key.is_empty() {
+        return (part, vec![]);
+    }
+    indexes.reverse();
+    (key, indexes)
+}
+
+/// zip the header paths and the record into a nested json object
+pub fn record_to_nested_row(
+    paths: &[Vec<PathSegment>],
+    record: &StringRecord,
+    types: &[ColumnType],
+) -> Result<Value> {
+    let values = record
+        .iter()
+        .zip(types.iter())
+        .map(|(value, ty)| typed_value(value, *ty));
+    values_to_nested_row(paths, values)
+}
+
+/// zip the header paths and the typed values into a nested json object
+pub fn values_to_nested_row(
+    paths: &[Vec<PathSegment>],
+    values: impl IntoIterator<Item = Value>,
+) -> Result<Value> {
+    let mut row = Value::Object(Map::new());
+    for (path, value) in paths.iter().zip(values) {
+        insert_path(&mut row, path, value)?;
+    }
+    Ok(row)
+}
+
+fn insert_path(root: &mut Value, path: &[PathSegment], value: Value) -> Result<()> {
+    let mut current = root;
+    for segment in path {
+        // 还没有值的节点根据下一级是key还是index初始化为object或array
+        if current.is_null() {
+            *current = match segment {
+                PathSegment::Key(_) => Value::Object(Map::new()),
+                PathSegment::Index(_) => Value::Array(vec![]),
+            };
+        }
+        current = match (segment, current) {
+            (PathSegment::Key(key), Value::Object(map)) => {
+                map.entry(key.clone()).or_insert(Value::Null)
+            }
+            (PathSegment::Index(idx), Value::Array(arr)) => {
+                if arr.len() <= *idx {
+                    arr.resize(idx + 1, Value::Null);
+                }
+                &mut arr[*idx]
+            }
+            _ => anyhow::bail!("conflicting header path: {}", display_path(path)),
+        };
+    }
+    if !current.is_null() {
+        anyhow::bail!("duplicated header path: {}", display_path(path));
+    }
+    *current = value;
+    Ok(())
+}
+
+fn display_path(path: &[PathSegment]) -> String {
+    let mut s = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) if s.is_empty() => s.push_str(key),
+            PathSegment::Key(key) => s.push_str(&format!(".{}", key)),
+            PathSegment::Index(idx) => s.push_str(&format!("[{}]", idx)),
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_header_path() {
+        use PathSegment::*;
+        assert_eq!(parse_header_path("name"), vec![Key("name".into())]);
+        assert_eq!(
+            parse_header_path("address.city"),
+            vec![Key("address".into()), Key("city".into())]
+        );
+        assert_eq!(
+            parse_header_path("tags[0][1]"),
+            vec![Key("tags".into()), Index(0), Index(1)]
+        );
+        assert_eq!(parse_header_path("a[x]"), vec![Key("a[x]".into())]);
+    }
+
+    #[test]
+    fn test_record_to_nested_row() -> Result<()> {
+        let header = ["name", "address.city", "address.zip", "tags[0]", "tags[2]"];
+        let paths = header.map(parse_header_path);
+        let record = StringRecord::from(vec!["a", "x", "1", "t0", "t2"]);
+        let mut types = vec![ColumnType::String; 5];
+        types[2] = ColumnType::Integer;
+        let row = record_to_nested_row(&paths, &record, &types)?;
+        assert_eq!(
+            row,
+            json!({"name": "a", "address": {"city": "x", "zip": 1}, "tags": ["t0", null, "t2"]})
+        );
+
+        let paths = ["a", "a.b"].map(parse_header_path);
+        let record = StringRecord::from(vec!["1", "2"]);
+        assert!(record_to_nested_row(&paths, &record, &types).is_err());
+        Ok(())
+    }
+}
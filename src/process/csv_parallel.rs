@@ -0,0 +1,538 @@
+use super::{
+    columnar_writer, merge_column_type, open_csv_input, open_output, resolve_sort_keys,
+    sort_records, update_column_types, CsvReader, Fragment, FragmentWriter, RowConverter,
+};
+use crate::cli::csv_opts::{ColumnType, CsvConvertOpts, CsvReaderOpts, OutputFormat, RaggedPolicy};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    io::{self, Read},
+    sync::mpsc,
+};
+
+/// 每一块的大小, 块总是在记录的边界切开
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// 每次从输入读取的字节数
+const READ_SIZE: u64 = 64 * 1024;
+/// 排序之后每个任务转换的行数
+const SORTED_BATCH: usize = 10_000;
+
+/// convert the csv on a thread pool, returns the number of converted rows.
+/// the input is split into chunks at record boundaries, the chunks are parsed, filtered and
+/// serialized by the workers, then written in the original order
+pub fn process_csv_parallel(opts: &CsvConvertOpts, input: &str, output: &str) -> Result<u64> {
+    convert_parallel(opts, input, output, CHUNK_SIZE)
+}
+
+fn convert_parallel(
+    opts: &CsvConvertOpts,
+    input: &str,
+    output: &str,
+    chunk_size: usize,
+) -> Result<u64> {
+    let mut builder = ThreadPoolBuilder::new();
+    if let Some(jobs) = opts.jobs {
+        builder = builder.num_threads(jobs);
+    }
+    let pool = builder.build()?;
+
+    let mut chunks = RecordChunks::new(
+        open_csv_input(input, &opts.reader)?,
+        &opts.reader,
+        chunk_size,
+    );
+    let first = chunks.next().transpose()?.unwrap_or_default();
+    let header = CsvReader::new(first.data.as_slice(), &opts.reader).headers()?;
+    let parser = ChunkParser::new(&opts.reader, header.len());
+    let mut chunks = std::iter::once(Ok(first)).chain(chunks);
+
+    let mut types = vec![ColumnType::String; header.len()];
+    let mut buffered = None;
+    // parquet和arrow的列有类型, 总是根据整个文件推断
+    let binary = matches!(opts.format, OutputFormat::Parquet | OutputFormat::Arrow);
+    if opts.infer || binary {
+        // 推断类型需要先扫描一遍, 标准输入只能读一次, 所以先把所有块读进内存
+        types = vec![ColumnType::Null; header.len()];
+        let mut merge = |chunk_types: Vec<ColumnType>| {
+            for (ty, chunk_ty) in types.iter_mut().zip(chunk_types) {
+                *ty = merge_column_type(*ty, chunk_ty);
+            }
+            Ok(())
+        };
+        let infer = |chunk: &Chunk| parser.infer_types(chunk);
+        if input == "-" {
+            let all = chunks.by_ref().collect::<io::Result<Vec<_>>>()?;
+            run_ordered(&pool, all.iter().map(Ok), &infer, &mut merge)?;
+            buffered = Some(all);
+        } else {
+            let again = RecordChunks::new(
+                open_csv_input(input, &opts.reader)?,
+                &opts.reader,
+                chunk_size,
+            );
+            run_ordered(&pool, again, &infer, &mut merge)?;
+        }
+    }
+    let converter = RowConverter::new(opts, &header, types)?;
+    let sort_keys = resolve_sort_keys(&header, &opts.sort_by)?;
+
+    let mut writer = if binary {
+        let columnar = columnar_writer(opts, &converter, output)?;
+        FragmentWriter::from_row_writer(opts.format, opts.out_delimiter, Box::new(columnar))
+    } else {
+        FragmentWriter::new(
+            opts.format,
+            opts.out_delimiter,
+            &opts.writer,
+            open_output(output, opts.out_encoding)?,
+        )
+    };
+    let mut count = 0;
+    let mut write = |fragment: Fragment| {
+        count += fragment.count;
+        writer.write_fragment(fragment)
+    };
+    if sort_keys.is_empty() {
+        let work = |chunk: &Chunk| -> Result<Fragment> {
+            let mut fragment = Fragment::new(opts.format, opts.out_delimiter);
+            parser.parse(chunk, |record| {
+                if converter.matches(record) {
+                    fragment.push(&converter.convert(record)?)?;
+                }
+                Ok(())
+            })?;
+            Ok(fragment)
+        };
+        match &buffered {
+            Some(all) => run_ordered(&pool, all.iter().map(Ok), &work, &mut write)?,
+            None => run_ordered(&pool, chunks, &work, &mut write)?,
+        }
+    } else {
+        // 排序需要所有的行, 并行解析和过滤之后排序, 再分批并行转换
+        let mut records = vec![];
+        let mut collect = |chunk_records: Vec<StringRecord>| {
+            records.extend(chunk_records);
+            Ok(())
+        };
+        let work = |chunk: &Chunk| parser.filter(chunk, &converter);
+        match &buffered {
+            Some(all) => run_ordered(&pool, all.iter().map(Ok), &work, &mut collect)?,
+            None => run_ordered(&pool, chunks, &work, &mut collect)?,
+        }
+        sort_records(&mut records, &sort_keys);
+        let batch = SORTED_BATCH * pool.current_num_threads();
+        for records in records.chunks(batch) {
+            let fragments = pool.install(|| {
+                records
+                    .par_chunks(SORTED_BATCH)
+                    .map(|records| {
+                        let mut fragment = Fragment::new(opts.format, opts.out_delimiter);
+                        for record in records {
+                            fragment.push(&converter.convert(record)?)?;
+                        }
+                        Ok(fragment)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            for fragment in fragments {
+                write(fragment)?;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+/// run `work` on the chunks in the thread pool and pass the results to `done` in the original order.
+/// the chunks are read ahead at most twice the number of threads, so the memory is bounded
+fn run_ordered<'a, C, T, F>(
+    pool: &ThreadPool,
+    mut chunks: impl Iterator<Item = io::Result<C>>,
+    work: &'a F,
+    done: &mut dyn FnMut(T) -> Result<()>,
+) -> Result<()>
+where
+    C: Borrow<Chunk> + Send + 'a,
+    T: Send + 'a,
+    F: Fn(&Chunk) -> Result<T> + Sync,
+{
+    let in_flight = pool.current_num_threads() * 2;
+    let (tx, rx) = mpsc::channel();
+    // 读取和写出都在当前线程, 解析和序列化在线程池里
+    pool.in_place_scope(|scope| {
+        let mut pending = BTreeMap::new();
+        let (mut sent, mut next) = (0, 0);
+        let mut eof = false;
+        loop {
+            while !eof && sent - next < in_flight {
+                match chunks.next() {
+                    Some(chunk) => {
+                        let (tx, chunk, idx) = (tx.clone(), chunk?, sent);
+                        scope.spawn(move |_| {
+                            let _ = tx.send((idx, work(chunk.borrow())));
+                        });
+                        sent += 1;
+                    }
+                    None => eof = true,
+                }
+            }
+            if next == sent {
+                return Ok(());
+            }
+            let (idx, result) = rx.recv()?;
+            pending.insert(idx, result);
+            while let Some(result) = pending.remove(&next) {
+                done(result?)?;
+                next += 1;
+            }
+        }
+    })
+}
+
+/// a piece of the decoded input which starts and ends at record boundaries
+#[derive(Debug, Default)]
+pub struct Chunk {
+    /// 这一块第一行的行号, 从1开始
+    pub line: u64,
+    pub data: Vec<u8>,
+}
+
+/// parse the chunks with the same dialect, only the first chunk starts with the header
+struct ChunkParser {
+    first: CsvReaderOpts,
+    rest: CsvReaderOpts,
+    width: usize,
+}
+
+impl ChunkParser {
+    fn new(opts: &CsvReaderOpts, width: usize) -> Self {
+        Self {
+            first: opts.clone(),
+            rest: CsvReaderOpts {
+                header: false,
+                ..opts.clone()
+            },
+            width,
+        }
+    }
+
+    fn parse(&self, chunk: &Chunk, mut f: impl FnMut(&StringRecord) -> Result<()>) -> Result<()> {
+        // 只有第一块是从文件开头开始的
+        let opts = if chunk.line == 1 {
+            &self.first
+        } else {
+            &self.rest
+        };
+        let mut reader = CsvReader::new(chunk.data.as_slice(), opts);
+        reader.set_width(self.width);
+        // 块里的行号从1开始, 换算成整个文件的行号
+        let line =
+            |position: Option<&csv::Position>| chunk.line + position.map_or(1, |p| p.line()) - 1;
+        let mut record = StringRecord::new();
+        loop {
+            match reader.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => {
+                    if let csv::ErrorKind::UnequalLengths { pos, len, .. } = e.kind() {
+                        return Err(self.unequal_lengths(line(pos.as_ref()), *len as usize));
+                    }
+                    let line = line(e.position());
+                    return Err(e).with_context(|| format!("at line {}", line));
+                }
+            }
+            // 除了第一块, csv只和块里的第一行比较列数, 每一行都要和表头比较
+            if self.first.ragged == RaggedPolicy::Reject && record.len() != self.width {
+                return Err(self.unequal_lengths(line(record.position()), record.len()));
+            }
+            f(&record)?;
+        }
+    }
+
+    fn unequal_lengths(&self, line: u64, len: usize) -> anyhow::Error {
+        anyhow::anyhow!(
+            "found record with {} fields at line {}, but the header has {} fields",
+            len,
+            line,
+            self.width
+        )
+    }
+
+    fn infer_types(&self, chunk: &Chunk) -> Result<Vec<ColumnType>> {
+        let mut types = vec![ColumnType::Null; self.width];
+        self.parse(chunk, |record| {
+            update_column_types(&mut types, record);
+            Ok(())
+        })?;
+        Ok(types)
+    }
+
+    /// the records of the chunk which pass `--where`
+    fn filter(&self, chunk: &Chunk, converter: &RowConverter) -> Result<Vec<StringRecord>> {
+        let mut records = vec![];
+        self.parse(chunk, |record| {
+            if converter.matches(record) {
+                records.push(record.clone());
+            }
+            Ok(())
+        })?;
+        Ok(records)
+    }
+}
+
+/// split the utf-8 csv stream into chunks of about `chunk_size` bytes at record boundaries.
+/// the quoting state is tracked, a newline inside a quoted field or a comment never splits a record
+pub struct RecordChunks<R> {
+    reader: R,
+    scanner: BoundaryScanner,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    /// buf里已经扫描过的字节数
+    scanned: usize,
+    /// 下一块的行号, 和扫描到的位置的行号
+    chunk_line: u64,
+    line: u64,
+    eof: bool,
+}
+
+impl<R: Read> RecordChunks<R> {
+    pub fn new(reader: R, opts: &CsvReaderOpts, chunk_size: usize) -> Self {
+        Self {
+            reader,
+            scanner: BoundaryScanner::new(opts),
+            chunk_size,
+            buf: vec![],
+            scanned: 0,
+            chunk_line: 1,
+            line: 1,
+            eof: false,
+        }
+    }
+
+    fn read_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        loop {
+            while self.scanned < self.buf.len() {
+                let b = self.buf[self.scanned];
+                self.scanned += 1;
+                if b == b'\n' {
+                    self.line += 1;
+                }
+                if self.scanner.step(b) && self.scanned >= self.chunk_size {
+                    return Ok(Some(self.split(self.scanned)));
+                }
+            }
+            if self.eof {
+                return Ok((!self.buf.is_empty()).then(|| self.split(self.buf.len())));
+            }
+            // 一条记录比chunk_size还长的时候继续读, 直到找到边界
+            let n = self
+                .reader
+                .by_ref()
+                .take(READ_SIZE)
+                .read_to_end(&mut self.buf)?;
+            self.eof = n == 0;
+        }
+    }
+
+    fn split(&mut self, at: usize) -> Chunk {
+        let rest = self.buf.split_off(at);
+        let data = std::mem::replace(&mut self.buf, rest);
+        self.scanned -= at;
+        let line = std::mem::replace(&mut self.chunk_line, self.line);
+        Chunk { line, data }
+    }
+}
+
+impl<R: Read> Iterator for RecordChunks<R> {
+    type Item = io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_chunk().transpose()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScanState {
+    RecordStart,
+    /// 记录以'\r'结束, 后面可能还有'\n'
+    RecordEnd,
+    FieldStart,
+    InField,
+    InQuoted,
+    /// 引号内遇到一个引号, 可能是结束, 也可能是两个引号的转义
+    QuoteInQuoted,
+    EscapeInQuoted,
+    Comment,
+}
+
+/// a minimal state machine following the csv dialect, only to find the end of the records
+struct BoundaryScanner {
+    state: ScanState,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    double_quote: bool,
+    comment: Option<u8>,
+}
+
+impl BoundaryScanner {
+    fn new(opts: &CsvReaderOpts) -> Self {
+        Self {
+            state: ScanState::RecordStart,
+            delimiter: opts.delimiter as u8,
+            quote: opts.quote as u8,
+            escape: opts.escape.map(|c| c as u8),
+            double_quote: opts.double_quote,
+            comment: opts.comment.map(|c| c as u8),
+        }
+    }
+
+    /// feed one byte, returns true if it is a newline ending a record
+    fn step(&mut self, b: u8) -> bool {
+        use ScanState::*;
+        let record_start = matches!(self.state, RecordStart | RecordEnd);
+        let (state, boundary) = match self.state {
+            Comment if b == b'\n' => (RecordStart, false),
+            Comment => (Comment, false),
+            InQuoted if Some(b) == self.escape => (EscapeInQuoted, false),
+            InQuoted if b == self.quote => (QuoteInQuoted, false),
+            InQuoted | EscapeInQuoted => (InQuoted, false),
+            QuoteInQuoted if b == self.quote && self.double_quote => (InQuoted, false),
+            _ if record_start && Some(b) == self.comment => (Comment, false),
+            // 引号只有在字段的开头才有特殊含义
+            RecordStart | RecordEnd | FieldStart if b == self.quote => (InQuoted, false),
+            _ if b == self.delimiter => (FieldStart, false),
+            // 空行和注释不算边界, 这样每一块至少有一条记录, 第一块一定有表头
+            _ if b == b'\n' => (RecordStart, self.state != RecordStart),
+            RecordStart if b == b'\r' => (RecordStart, false),
+            _ if b == b'\r' => (RecordEnd, false),
+            _ => (InField, false),
+        };
+        self.state = state;
+        boundary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::process_csv;
+    use clap::Parser;
+
+    #[test]
+    fn test_record_chunks() -> Result<()> {
+        let input = "# a \"comment\n\
+                    name,note\n\
+                    a,\"multi\nline \"\"quoted\"\"\n\"\n\
+                    b,plain\"quote\r\n\
+                    c,\"x\"\n\
+                    d,last";
+        let opts = CsvReaderOpts::parse_from(["csv", "--comment", "#"]);
+        let chunks =
+            RecordChunks::new(input.as_bytes(), &opts, 1).collect::<io::Result<Vec<_>>>()?;
+        let lines = chunks.iter().map(|chunk| chunk.line).collect::<Vec<_>>();
+        assert_eq!(lines, [1, 3, 6, 7, 8]);
+        let data = chunks
+            .iter()
+            .flat_map(|c| c.data.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(data, input.as_bytes());
+
+        // 每一块单独解析的结果和整个文件一样
+        let parser = ChunkParser::new(&opts, 2);
+        let mut records = vec![];
+        for chunk in &chunks {
+            parser.parse(chunk, |record| {
+                records.push(record.clone());
+                Ok(())
+            })?;
+        }
+        let expected = CsvReader::new(input.as_bytes(), &opts)
+            .records()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records, expected);
+        assert_eq!(&records[0][1], "multi\nline \"quoted\"\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_ragged_rows() -> Result<()> {
+        // 第二块正好从列数变少的那一行开始
+        let input = "a,b,c\n1,x,y\n2,x,y\n3,z\n4,z\n";
+        let path = std::env::temp_dir().join("rcli_test_parallel_ragged.csv");
+        std::fs::write(&path, input)?;
+        let path = path.to_str().unwrap();
+        let opts = CsvReaderOpts::default();
+        let lines = RecordChunks::new(input.as_bytes(), &opts, 18)
+            .map(|chunk| chunk.map(|chunk| chunk.line))
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(lines, [1, 4]);
+
+        let output = std::env::temp_dir().join("rcli_test_parallel_ragged.json");
+        let output = output.to_str().unwrap();
+        let opts = CsvConvertOpts::parse_from(["csv", "-i", path, "--parallel"]);
+        let err = convert_parallel(&opts, path, output, 18).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found record with 2 fields at line 4, but the header has 3 fields"
+        );
+        // 块里后面的行列数不一样, 也是整个文件的行号
+        std::fs::write(path, "a,b\n1,x\n2,y\n3,z,w\n")?;
+        let err = convert_parallel(&opts, path, output, 8).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found record with 3 fields at line 4, but the header has 2 fields"
+        );
+
+        let opts = CsvConvertOpts::parse_from(["csv", "-i", path, "--ragged", "fill"]);
+        let sequential = std::env::temp_dir().join("rcli_test_ragged_sequential.json");
+        process_csv(&opts, sequential.to_str().unwrap())?;
+        convert_parallel(&opts, path, output, 8)?;
+        assert_eq!(std::fs::read(sequential)?, std::fs::read(output)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_parallel() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let cases: [&[&str]; 6] = [
+            &["--format", "json", "--infer"],
+            &["--format", "csv", "--nested"],
+            &[
+                "--format",
+                "ndjson",
+                "--as-arrays",
+                "--where",
+                "Kit Number > 10",
+            ],
+            &[
+                "--format",
+                "yaml",
+                "--sort-by",
+                "Nationality,Kit Number:desc",
+            ],
+            &["--format", "tsv", "--where", "Kit Number > 100"],
+            &["--format", "parquet", "--infer", "--row-group-size", "10"],
+        ];
+        for (idx, args) in cases.iter().enumerate() {
+            let args = ["csv", "-i", "assets/juventus.csv"]
+                .iter()
+                .chain(args.iter());
+            let opts = CsvConvertOpts::parse_from(args.clone());
+            let parallel_opts =
+                CsvConvertOpts::parse_from(args.chain(&["--parallel", "--jobs", "3"]));
+            let sequential = dir.join(format!("rcli_test_sequential_{}", idx));
+            let parallel = dir.join(format!("rcli_test_parallel_{}", idx));
+            let sequential = sequential.to_str().unwrap();
+            let parallel = parallel.to_str().unwrap();
+            process_csv(&opts, sequential)?;
+            // 很小的块, 让每一块只有几行
+            convert_parallel(&parallel_opts, "assets/juventus.csv", parallel, 100)?;
+            assert_eq!(std::fs::read(sequential)?, std::fs::read(parallel)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use super::{infer_value_type, typed_value, value_to_cell, Blake3, Chacha20, KeyLoader};
+use crate::cli::csv_opts::{ColumnType, CsvConvertOpts};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde_json::Value;
+use std::sync::Arc;
+
+enum Protection {
+    Encrypt(Arc<Chacha20>),
+    Decrypt(Arc<Chacha20>),
+    Tokenize(Arc<Blake3>),
+}
+
+/// encrypt, decrypt or tokenize the values of some output columns, null and empty values are kept
+pub struct ColumnProtector {
+    columns: Vec<(usize, String, Protection)>,
+    infer: bool,
+}
+
+impl ColumnProtector {
+    /// `header` is the output header after `--select` or `--mapping`, None if no column is protected
+    pub fn new(opts: &CsvConvertOpts, header: &StringRecord) -> Result<Option<Self>> {
+        let cipher = opts
+            .encrypt_key
+            .as_deref()
+            .map(Chacha20::load)
+            .transpose()?
+            .map(Arc::new);
+        let tokenizer = opts
+            .token_key
+            .as_deref()
+            .map(Blake3::load)
+            .transpose()?
+            .map(Arc::new);
+        let cipher = || {
+            cipher.clone().ok_or_else(|| {
+                anyhow::anyhow!("--encrypt-key is required to encrypt or decrypt columns")
+            })
+        };
+        let tokenizer = || {
+            tokenizer
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("--token-key is required to tokenize columns"))
+        };
+        let mut protections = vec![];
+        for name in &opts.encrypt_columns {
+            protections.push((name, Protection::Encrypt(cipher()?)));
+        }
+        for name in &opts.decrypt_columns {
+            protections.push((name, Protection::Decrypt(cipher()?)));
+        }
+        for name in &opts.tokenize_columns {
+            protections.push((name, Protection::Tokenize(tokenizer()?)));
+        }
+        if protections.is_empty() {
+            return Ok(None);
+        }
+
+        let mut columns: Vec<(usize, String, Protection)> = vec![];
+        for (name, protection) in protections {
+            let idx = header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow::anyhow!("column not found: {}", name))?;
+            if columns.iter().any(|(i, _, _)| *i == idx) {
+                anyhow::bail!("column {} can only be protected once", name);
+            }
+            columns.push((idx, name.clone(), protection));
+        }
+        Ok(Some(Self {
+            columns,
+            infer: opts.infer,
+        }))
+    }
+
+    /// the types of the protected columns for typed outputs, the decrypted values are
+    /// inferred one by one so they can have different types
+    pub fn update_types(&self, types: &mut [Option<ColumnType>]) {
+        for (idx, _, protection) in &self.columns {
+            if let Some(ty) = types.get_mut(*idx) {
+                *ty = match protection {
+                    Protection::Decrypt(_) if self.infer => None,
+                    _ => Some(ColumnType::String),
+                };
+            }
+        }
+    }
+
+    /// replace the values of the protected columns in place
+    pub fn apply(&self, values: &mut [Value]) -> Result<()> {
+        for (idx, name, protection) in &self.columns {
+            let Some(value) = values.get_mut(*idx) else {
+                continue;
+            };
+            let cell = value_to_cell(value);
+            if cell.is_empty() {
+                continue;
+            }
+            *value = match protection {
+                Protection::Encrypt(cipher) => Value::String(cipher.encrypt_field(&cell)?),
+                Protection::Tokenize(tokenizer) => Value::String(tokenizer.tokenize(&cell)),
+                Protection::Decrypt(cipher) => {
+                    let plaintext = cipher
+                        .decrypt_field(&cell)
+                        .with_context(|| format!("can't decrypt column {}: {}", name, cell))?;
+                    // 加密后的列是字符串, 解密后再推断类型
+                    if self.infer {
+                        typed_value(&plaintext, infer_value_type(&plaintext))
+                    } else {
+                        Value::String(plaintext)
+                    }
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::process_csv;
+    use super::*;
+    use clap::Parser;
+
+    fn convert(args: &[&str], output: &str) -> Result<Vec<Value>> {
+        let args = ["csv", "--format", "csv"].iter().chain(args);
+        process_csv(&CsvConvertOpts::parse_from(args), output)?;
+        let mut reader = csv::Reader::from_path(output)?;
+        let header = reader.headers()?.clone();
+        let mut rows = vec![];
+        for record in reader.records() {
+            rows.push(header.iter().zip(record?.iter()).collect());
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    fn test_protect_columns() -> Result<()> {
+        let encrypted = std::env::temp_dir().join("rcli_test_encrypted.csv");
+        let encrypted = encrypted.to_str().unwrap();
+        let rows = convert(
+            &[
+                "-i",
+                "assets/juventus.csv",
+                "--encrypt-columns",
+                "Name,Kit Number",
+                "--encrypt-key",
+                "fixtures/chacha20.txt",
+                "--tokenize-columns",
+                "Nationality",
+                "--token-key",
+                "fixtures/blake3.txt",
+            ],
+            encrypted,
+        )?;
+        assert_ne!(rows[0]["Name"], "Wojciech Szczesny");
+        assert_eq!(rows[0]["Position"], "Goalkeeper");
+        // Szczesny和Perin的国籍不同, Perin和Buffon都是意大利
+        let token = Blake3::load("fixtures/blake3.txt")?.tokenize("Italy");
+        assert_eq!(rows[1]["Nationality"], token.as_str());
+        assert_eq!(rows[1]["Nationality"], rows[2]["Nationality"]);
+        assert_ne!(rows[0]["Nationality"], rows[1]["Nationality"]);
+
+        let decrypted = std::env::temp_dir().join("rcli_test_decrypted.json");
+        let decrypted = decrypted.to_str().unwrap();
+        let opts = CsvConvertOpts::parse_from([
+            "csv",
+            "-i",
+            encrypted,
+            "--decrypt-columns",
+            "Name,Kit Number",
+            "--encrypt-key",
+            "fixtures/chacha20.txt",
+            "--infer",
+        ]);
+        process_csv(&opts, decrypted)?;
+        let ret: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(decrypted)?)?;
+        assert_eq!(ret[0]["Name"], "Wojciech Szczesny");
+        assert_eq!(ret[0]["Kit Number"], 1);
+        assert_eq!(ret[1]["Nationality"], token.as_str());
+
+        // 随机的nonce: 相同的值每次加密的结果都不一样
+        let cipher = Chacha20::load("fixtures/chacha20.txt")?;
+        let (a, b) = (cipher.encrypt_field("x")?, cipher.encrypt_field("x")?);
+        assert_ne!(a, b);
+        assert_eq!(cipher.decrypt_field(&b)?, "x");
+        assert!(cipher.decrypt_field("abc").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_protect_short_key() -> Result<()> {
+        let key = std::env::temp_dir().join("rcli_test_short.key");
+        std::fs::write(&key, "too short")?;
+        let key = key.to_str().unwrap();
+        let output = std::env::temp_dir().join("rcli_test_short_key.csv");
+        for (columns, key_opt) in [
+            ("--encrypt-columns", "--encrypt-key"),
+            ("--tokenize-columns", "--token-key"),
+        ] {
+            let ret = convert(
+                &["-i", "assets/juventus.csv", columns, "Name", key_opt, key],
+                output.to_str().unwrap(),
+            );
+            let err = ret.unwrap_err().to_string();
+            assert!(err.contains("at least 32 bytes"), "{}", err);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use super::decode_reader;
+use crate::{
+    cli::csv_opts::{CsvReaderOpts, RaggedPolicy, TrimMode},
+    utils::get_reader,
+};
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use encoding_rs::UTF_8;
+use std::{collections::HashSet, io::Read};
+
+/// csv reader with the shared reader options applied: dialect, header names and ragged rows
+pub struct CsvReader<R: Read = Box<dyn Read>> {
+    reader: Reader<R>,
+    opts: CsvReaderOpts,
+    /// `--ragged fill`补齐的列数, 默认是表头的列数
+    width: Option<usize>,
+}
+
+/// build the csv reader with the shared reader options, the input is decoded into utf-8.
+/// '-' 代表从标准输入读取
+pub fn build_csv_reader(input: &str, opts: &CsvReaderOpts) -> anyhow::Result<CsvReader> {
+    // let mut reader = Reader::from_path(input)?;
+    Ok(CsvReader::new(open_csv_input(input, opts)?, opts))
+}
+
+/// open the input and decode it into utf-8, without parsing
+pub fn open_csv_input(input: &str, opts: &CsvReaderOpts) -> anyhow::Result<Box<dyn Read>> {
+    let (file, encoding) = decode_reader(get_reader(input)?, opts.encoding)?;
+    if opts.encoding.is_none() && encoding != UTF_8 {
+        eprintln!("detected encoding: {}", encoding.name());
+    }
+    Ok(file)
+}
+
+impl<R: Read> CsvReader<R> {
+    pub fn new(reader: R, opts: &CsvReaderOpts) -> Self {
+        let trim = match opts.trim {
+            TrimMode::None => Trim::None,
+            TrimMode::Headers => Trim::Headers,
+            TrimMode::Fields => Trim::Fields,
+            TrimMode::All => Trim::All,
+        };
+        let reader = ReaderBuilder::new()
+            .delimiter(opts.delimiter as u8)
+            .has_headers(opts.header)
+            .quote(opts.quote as u8)
+            .escape(opts.escape.map(|c| c as u8))
+            .double_quote(opts.double_quote)
+            .comment(opts.comment.map(|c| c as u8))
+            .trim(trim)
+            .flexible(opts.ragged == RaggedPolicy::Fill)
+            .from_reader(reader);
+        Self {
+            reader,
+            opts: opts.clone(),
+            width: None,
+        }
+    }
+
+    /// set the width used by `--ragged fill`, for the chunks of a file which don't start with the header
+    pub fn set_width(&mut self, width: usize) {
+        self.width = Some(width);
+    }
+
+    /// the column names: `--columns` if given, the header row, or `col1, col2, ...` for headerless files.
+    /// for headerless files the first record is only peeked, it is still returned by `records()`
+    pub fn headers(&mut self) -> anyhow::Result<StringRecord> {
+        // 没有表头的时候csv把第一行暂存在headers里, 不能用set_headers覆盖, 否则第一行数据就丢了
+        let header = self.reader.headers()?;
+        let width = header.len();
+        let header = self.opts.header.then_some(header);
+        column_names(header, width, &self.opts)
+    }
+
+    pub fn read_record(&mut self, record: &mut StringRecord) -> csv::Result<bool> {
+        let width = match self.width {
+            Some(width) => width,
+            None => self.reader.headers()?.len(),
+        };
+        let ok = self.reader.read_record(record)?;
+        fill_record(record, width, self.opts.ragged);
+        Ok(ok)
+    }
+
+    pub fn records(&mut self) -> impl Iterator<Item = csv::Result<StringRecord>> + '_ {
+        // 读表头出错的时候records会返回同样的错误
+        let width = self
+            .width
+            .unwrap_or_else(|| self.reader.headers().map_or(0, |h| h.len()));
+        let ragged = self.opts.ragged;
+        self.reader.records().map(move |record| {
+            record.map(|mut record| {
+                fill_record(&mut record, width, ragged);
+                record
+            })
+        })
+    }
+}
+
+/// resolve the column names: `--columns` if given, the header row, or `col1, col2, ...`
+pub fn column_names(
+    header: Option<&StringRecord>,
+    width: usize,
+    opts: &CsvReaderOpts,
+) -> anyhow::Result<StringRecord> {
+    let columns = &opts.columns;
+    match header {
+        Some(header) if columns.is_empty() => Ok(header.clone()),
+        _ if columns.is_empty() => Ok((1..=width).map(|idx| format!("col{}", idx)).collect()),
+        _ if columns.len() == width || width == 0 => Ok(StringRecord::from(columns.clone())),
+        _ => anyhow::bail!(
+            "--columns has {} names, but the csv has {} columns",
+            columns.len(),
+            width
+        ),
+    }
+}
+
+/// make the column names unique, the later duplicates get a suffix: `name_2`, `name_3`, ...
+/// the suffix never collides with another column
+pub fn unique_column_names<S: AsRef<str>>(names: &[S]) -> Vec<String> {
+    let taken = names
+        .iter()
+        .map(|name| name.as_ref())
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref();
+            if seen.insert(name.to_string()) {
+                return name.to_string();
+            }
+            (2..)
+                .map(|n| format!("{}_{}", name, n))
+                .find(|candidate| {
+                    !taken.contains(candidate.as_str()) && seen.insert(candidate.clone())
+                })
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// `--ragged fill`: pad the missing trailing fields with empty values, drop the extra fields
+fn fill_record(record: &mut StringRecord, width: usize, ragged: RaggedPolicy) {
+    if ragged != RaggedPolicy::Fill || record.len() == width || record.is_empty() {
+        return;
+    }
+    if record.len() > width {
+        record.truncate(width);
+    }
+    while record.len() < width {
+        record.push_field("");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::csv_opts::CsvReaderOpts;
+    use clap::Parser;
+
+    #[test]
+    fn test_unique_column_names() {
+        assert_eq!(
+            unique_column_names(&["a", "b", "a", "a_2", "a"]),
+            vec!["a", "b", "a_3", "a_2", "a_4"]
+        );
+    }
+
+    #[test]
+    fn test_csv_reader_headers() -> anyhow::Result<()> {
+        let data = "1,a\n2,b\n";
+        let opts = CsvReaderOpts {
+            header: false,
+            ..Default::default()
+        };
+        let mut reader = CsvReader::new(data.as_bytes(), &opts);
+        assert_eq!(reader.headers()?, vec!["col1", "col2"]);
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[0][1], "a");
+
+        let opts = CsvReaderOpts {
+            header: false,
+            columns: vec!["id".to_string(), "name".to_string()],
+            ..Default::default()
+        };
+        let mut reader = CsvReader::new(data.as_bytes(), &opts);
+        assert_eq!(reader.headers()?, vec!["id", "name"]);
+        assert_eq!(reader.records().count(), 2);
+
+        // 有表头的时候--columns替换原来的表头, 个数必须一致
+        let opts = CsvReaderOpts {
+            columns: vec!["id".to_string()],
+            ..Default::default()
+        };
+        let mut reader = CsvReader::new(data.as_bytes(), &opts);
+        assert!(reader.headers().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_reader_dialect() -> anyhow::Result<()> {
+        let data = "# exported at 2024-01-01\nname; note ;age\n a ;\"x\\\"y\";1\nb;z\n";
+        let opts = CsvReaderOpts::parse_from([
+            "csv",
+            "-d",
+            ";",
+            "--comment",
+            "#",
+            "--escape",
+            "\\",
+            "--no-double-quote",
+            "--trim",
+            "all",
+            "--ragged",
+            "fill",
+        ]);
+        let mut reader = CsvReader::new(data.as_bytes(), &opts);
+        assert_eq!(reader.headers()?, vec!["name", "note", "age"]);
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records[0], vec!["a", "x\"y", "1"]);
+        assert_eq!(records[1], vec!["b", "z", ""]);
+
+        // 默认拒绝列数不一致的行, 错误信息里有行号和字节偏移
+        let opts = CsvReaderOpts::parse_from(["csv", "-d", ";", "--comment", "#"]);
+        let mut reader = CsvReader::new(data.as_bytes(), &opts);
+        let err = reader
+            .records()
+            .find_map(Result::err)
+            .expect("ragged row should be rejected");
+        let message = err.to_string();
+        assert!(message.contains("line: 4") && message.contains("byte:"));
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use super::{build_csv_reader, render_table};
+use crate::cli::csv_opts::CsvShowOpts;
+use anyhow::Result;
+use csv::StringRecord;
+use std::collections::VecDeque;
+
+pub fn process_csv_show(opts: &CsvShowOpts) -> Result<String> {
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    let indexes = select_columns(&header, &opts.select)?;
+
+    let records = reader.records();
+    let records = if let Some(tail) = opts.tail {
+        // tail只需要保留最后N行
+        let mut last = VecDeque::with_capacity(tail);
+        for record in records {
+            last.push_back(record?);
+            if last.len() > tail {
+                last.pop_front();
+            }
+        }
+        last.into()
+    } else {
+        let (start, end) = match (opts.head, opts.range) {
+            (Some(head), _) => (0, Some(head)),
+            (None, Some(range)) => range,
+            (None, None) => (0, None),
+        };
+        let count = end.map_or(usize::MAX, |end| end - start);
+        // take 会在读够之后停止, 不需要读完整个文件
+        records
+            .skip(start)
+            .take(count)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let headers = indexes
+        .iter()
+        .map(|&idx| header[idx].to_string())
+        .collect::<Vec<_>>();
+    let rows = records
+        .iter()
+        .map(|record| {
+            indexes
+                .iter()
+                .map(|&idx| record.get(idx).unwrap_or_default().to_string())
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    Ok(render_table(&headers, &rows, opts.max_width))
+}
+
+/// find the indexes of the selected columns, all columns if nothing is selected
+pub fn select_columns(header: &StringRecord, select: &[String]) -> Result<Vec<usize>> {
+    if select.is_empty() {
+        return Ok((0..header.len()).collect());
+    }
+    select
+        .iter()
+        .map(|name| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow::anyhow!("column not found: {}", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn show(args: &[&str]) -> Result<String> {
+        let args = ["show", "-i", "assets/juventus.csv"].iter().chain(args);
+        process_csv_show(&CsvShowOpts::parse_from(args))
+    }
+
+    #[test]
+    fn test_process_csv_show() -> Result<()> {
+        let table = show(&["--head", "2", "--select", "Name,Kit Number"])?;
+        let expected = "\
++-------------------+------------+
+| Name              | Kit Number |
++-------------------+------------+
+| Wojciech Szczesny | 1          |
+| Mattia Perin      | 37         |
++-------------------+------------+
+";
+        assert_eq!(table, expected);
+
+        let table = show(&["--tail", "1", "--select", "Name"])?;
+        assert_eq!(table.lines().count(), 5);
+        let table = show(&["--range", "1..3", "--select", "Name", "--max-width", "6"])?;
+        assert!(table.contains("| Matti… |"));
+        assert!(show(&["--select", "Age"]).is_err());
+        Ok(())
+    }
+}
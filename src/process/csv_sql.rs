@@ -0,0 +1,221 @@
+use super::{
+    build_csv_reader, infer_column_types, new_row_writer, typed_value, unique_column_names,
+};
+use crate::{
+    cli::csv_opts::{ColumnType, CsvReaderOpts, CsvSqlOpts},
+    utils::get_writer,
+};
+use anyhow::Result;
+use csv::StringRecord;
+use rusqlite::{
+    params_from_iter,
+    types::{Value as SqlValue, ValueRef},
+    Connection,
+};
+use serde_json::{Map, Value};
+
+pub fn process_csv_sql(opts: &CsvSqlOpts) -> Result<()> {
+    let conn = Connection::open_in_memory()?;
+    for (table, path) in &opts.input {
+        load_csv_table(&conn, table, path, &opts.reader)?;
+    }
+
+    let mut stmt = conn.prepare(&opts.query)?;
+    // `SELECT * FROM a JOIN b` 或者两个 `count(*)` 会有重复的列名, 重复的加上后缀
+    let columns = unique_column_names(&stmt.column_names());
+    let mut writer = new_row_writer(
+        opts.format,
+        opts.out_delimiter,
+        &opts.writer,
+        get_writer(&opts.output)?,
+    );
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let mut obj = Map::new();
+        for (idx, column) in columns.iter().enumerate() {
+            obj.insert(column.clone(), sql_to_json(row.get_ref(idx)?));
+        }
+        writer.write_row(&Value::Object(obj))?;
+        count += 1;
+    }
+    writer.finish()?;
+    eprintln!("{} rows returned", count);
+    Ok(())
+}
+
+/// load a csv file as a sqlite table, the column types are inferred
+pub fn load_csv_table(
+    conn: &Connection,
+    table: &str,
+    path: &str,
+    opts: &CsvReaderOpts,
+) -> Result<()> {
+    let mut reader = build_csv_reader(path, opts)?;
+    let header = reader.headers()?;
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    let types = infer_column_types(&records, header.len());
+    insert_table(conn, table, &header, &records, &types)
+}
+
+/// create the table and insert the records in one transaction,
+/// duplicated column names get a suffix like the output columns of `rcli csv sql`
+pub fn insert_table(
+    conn: &Connection,
+    table: &str,
+    header: &StringRecord,
+    records: &[StringRecord],
+    types: &[ColumnType],
+) -> Result<()> {
+    if header.is_empty() {
+        anyhow::bail!("can't create table {}: the csv has no columns", table);
+    }
+    let names = unique_column_names(&header.iter().collect::<Vec<_>>());
+    let columns = names
+        .iter()
+        .zip(types)
+        .map(|(name, ty)| format!("{} {}", quote_ident(name), sql_type(*ty)))
+        .collect::<Vec<_>>();
+    conn.execute(
+        &format!(
+            "CREATE TABLE {} ({})",
+            quote_ident(table),
+            columns.join(", ")
+        ),
+        [],
+    )?;
+
+    let placeholders = (1..=header.len())
+        .map(|idx| format!("?{}", idx))
+        .collect::<Vec<_>>();
+    let insert = format!(
+        "INSERT INTO {} VALUES ({})",
+        quote_ident(table),
+        placeholders.join(", ")
+    );
+    // 在一个事务里插入, 否则每一行都会提交一次, 非常慢
+    conn.execute_batch("BEGIN")?;
+    {
+        let mut stmt = conn.prepare(&insert)?;
+        for record in records {
+            let values = record
+                .iter()
+                .zip(types)
+                .map(|(value, ty)| json_to_sql(typed_value(value, *ty)));
+            stmt.execute(params_from_iter(values))?;
+        }
+    }
+    conn.execute_batch("COMMIT")?;
+    Ok(())
+}
+
+/// quote the identifier with double quotes, eg: Kit Number => "Kit Number"
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// the sqlite column type
+pub fn sql_type(ty: ColumnType) -> &'static str {
+    match ty {
+        ColumnType::Integer | ColumnType::Boolean => "INTEGER",
+        ColumnType::Float => "REAL",
+        ColumnType::Null | ColumnType::String => "TEXT",
+    }
+}
+
+fn json_to_sql(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn sql_to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(t) | ValueRef::Blob(t) => Value::String(String::from_utf8_lossy(t).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_load_csv_table() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        let opts = CsvReaderOpts::default();
+        load_csv_table(&conn, "juventus", "assets/juventus.csv", &opts)?;
+        let (nationality, count): (String, i64) = conn.query_row(
+            "SELECT Nationality, count(*) FROM juventus GROUP BY 1 ORDER BY 2 DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((nationality.as_str(), count), ("Italy", 8));
+        // Kit Number 是INTEGER, 可以直接比较大小
+        let count: i64 = conn.query_row(
+            "SELECT count(*) FROM juventus WHERE \"Kit Number\" > 30",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_table_header() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        let header = StringRecord::from(vec!["a", "a", "a_2"]);
+        let records = [StringRecord::from(vec!["1", "2", "3"])];
+        insert_table(&conn, "t", &header, &records, &[ColumnType::Integer; 3])?;
+        // 第二个a变成a_3, 因为a_2已经有了
+        let (a, a_3, a_2): (i64, i64, i64) =
+            conn.query_row("SELECT a, a_3, a_2 FROM t", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+        assert_eq!((a, a_3, a_2), (1, 2, 3));
+
+        let err = insert_table(&conn, "empty", &StringRecord::new(), &[], &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "can't create table empty: the csv has no columns"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql_duplicated_columns() -> Result<()> {
+        let output = std::env::temp_dir().join("rcli_test_sql_duplicated.json");
+        let output = output.to_str().unwrap();
+        let opts = <CsvSqlOpts as clap::Parser>::parse_from([
+            "sql",
+            "SELECT a.Name, b.Name, count(*), count(*) FROM j a JOIN j b USING(Name) LIMIT 1",
+            "-i",
+            "j=assets/juventus.csv",
+            "-o",
+            output,
+        ]);
+        process_csv_sql(&opts)?;
+        let ret: Vec<Value> = serde_json::from_str(&std::fs::read_to_string(output)?)?;
+        let columns = ret[0].as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(columns, vec!["Name", "Name_2", "count(*)", "count(*)_2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql_value_convert() {
+        assert_eq!(json_to_sql(json!(1)), SqlValue::Integer(1));
+        assert_eq!(json_to_sql(json!(true)), SqlValue::Integer(1));
+        assert_eq!(sql_to_json(ValueRef::Real(1.5)), json!(1.5));
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+}
@@ -0,0 +1,387 @@
+use super::{build_csv_reader, infer_value_type, merge_column_type, parse_number, render_table};
+use crate::cli::csv_opts::{ColumnType, CsvStatsOpts, ReportFormat};
+use anyhow::Result;
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// HyperLogLog的精度, 2^12个寄存器, 标准误差约1.6%
+const HLL_PRECISION: u32 = 12;
+/// approx模式下每一列最多保留的候选值个数
+const MIN_TOP_CAPACITY: usize = 1000;
+
+#[derive(Debug, Serialize)]
+pub struct ColumnStats {
+    pub column: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// non-null values
+    pub count: u64,
+    pub nulls: u64,
+    pub distinct: u64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub mean: Option<f64>,
+    pub top: Vec<TopValue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopValue {
+    pub value: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CsvStats {
+    pub rows: u64,
+    /// distinct count and top values are approximate
+    pub approximate: bool,
+    pub columns: Vec<ColumnStats>,
+}
+
+pub fn process_csv_stats(opts: &CsvStatsOpts) -> Result<String> {
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    let stats = profile_records(&header, reader.records(), opts.top, opts.approx)?;
+    let report = match opts.format {
+        ReportFormat::Json => serde_json::to_string_pretty(&stats)? + "\n",
+        ReportFormat::Table => render_stats(&stats),
+    };
+    Ok(report)
+}
+
+/// profile the records column by column, only one pass is needed
+pub fn profile_records(
+    header: &StringRecord,
+    records: impl Iterator<Item = csv::Result<StringRecord>>,
+    top: usize,
+    approx: bool,
+) -> Result<CsvStats> {
+    let mut profilers = header
+        .iter()
+        .map(|_| ColumnProfiler::new(approx, top))
+        .collect::<Vec<_>>();
+    let mut rows = 0;
+    for record in records {
+        let record = record?;
+        for (profiler, value) in profilers.iter_mut().zip(record.iter()) {
+            profiler.add(value);
+        }
+        rows += 1;
+    }
+    let columns = header
+        .iter()
+        .zip(profilers)
+        .map(|(column, profiler)| profiler.finish(column, top))
+        .collect();
+    Ok(CsvStats {
+        rows,
+        approximate: approx,
+        columns,
+    })
+}
+
+fn render_stats(stats: &CsvStats) -> String {
+    let headers = [
+        "column", "type", "count", "nulls", "distinct", "min", "max", "mean", "top",
+    ]
+    .map(String::from);
+    let cell = |v: &Option<Value>| v.as_ref().map(value_to_string).unwrap_or_default();
+    let rows = stats
+        .columns
+        .iter()
+        .map(|c| {
+            let top = c
+                .top
+                .iter()
+                .map(|t| format!("{}({})", t.value, t.count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let distinct = if stats.approximate {
+                format!("~{}", c.distinct)
+            } else {
+                c.distinct.to_string()
+            };
+            vec![
+                c.column.clone(),
+                c.ty.clone(),
+                c.count.to_string(),
+                c.nulls.to_string(),
+                distinct,
+                cell(&c.min),
+                cell(&c.max),
+                c.mean.map(|m| format!("{:.2}", m)).unwrap_or_default(),
+                top,
+            ]
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "rows: {}\n{}",
+        stats.rows,
+        render_table(&headers, &rows, None)
+    )
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+struct ColumnProfiler {
+    ty: ColumnType,
+    count: u64,
+    nulls: u64,
+    sum: f64,
+    min_num: Option<f64>,
+    max_num: Option<f64>,
+    /// 整数列的最小最大值, 超过2^53的整数转成浮点数会丢失精度
+    min_int: Option<i64>,
+    max_int: Option<i64>,
+    min_str: Option<String>,
+    max_str: Option<String>,
+    distinct: DistinctCounter,
+}
+
+impl ColumnProfiler {
+    fn new(approx: bool, top: usize) -> Self {
+        let distinct = if approx {
+            DistinctCounter::Approx {
+                hll: HyperLogLog::new(),
+                frequent: FrequentItems::new(MIN_TOP_CAPACITY.max(top * 20)),
+            }
+        } else {
+            DistinctCounter::Exact(HashMap::new())
+        };
+        Self {
+            ty: ColumnType::Null,
+            count: 0,
+            nulls: 0,
+            sum: 0.0,
+            min_num: None,
+            max_num: None,
+            min_int: None,
+            max_int: None,
+            min_str: None,
+            max_str: None,
+            distinct,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        let ty = infer_value_type(value);
+        self.ty = merge_column_type(self.ty, ty);
+        if ty == ColumnType::Null {
+            self.nulls += 1;
+            return;
+        }
+        self.count += 1;
+        // 和推断的规则一样, "NaN" "inf" 不是数字
+        if let Some(num) = parse_number(value) {
+            self.sum += num;
+            self.min_num = Some(self.min_num.map_or(num, |m| m.min(num)));
+            self.max_num = Some(self.max_num.map_or(num, |m| m.max(num)));
+        }
+        if let Ok(int) = value.trim().parse::<i64>() {
+            self.min_int = Some(self.min_int.map_or(int, |m| m.min(int)));
+            self.max_int = Some(self.max_int.map_or(int, |m| m.max(int)));
+        }
+        if self.min_str.as_deref().is_none_or(|m| value < m) {
+            self.min_str = Some(value.to_string());
+        }
+        if self.max_str.as_deref().is_none_or(|m| value > m) {
+            self.max_str = Some(value.to_string());
+        }
+        self.distinct.add(value);
+    }
+
+    fn finish(self, column: &str, top: usize) -> ColumnStats {
+        let (min, max, mean) = match self.ty {
+            ColumnType::Integer => (
+                self.min_int.map(Value::from),
+                self.max_int.map(Value::from),
+                Some(self.sum / self.count as f64),
+            ),
+            ColumnType::Float => (
+                self.min_num.map(Value::from),
+                self.max_num.map(Value::from),
+                Some(self.sum / self.count as f64),
+            ),
+            _ => (
+                self.min_str.map(Value::String),
+                self.max_str.map(Value::String),
+                None,
+            ),
+        };
+        let (distinct, top) = self.distinct.finish(top);
+        ColumnStats {
+            column: column.to_string(),
+            ty: self.ty.to_string(),
+            count: self.count,
+            nulls: self.nulls,
+            distinct,
+            min,
+            max,
+            mean,
+            top,
+        }
+    }
+}
+
+enum DistinctCounter {
+    Exact(HashMap<String, u64>),
+    Approx {
+        hll: HyperLogLog,
+        frequent: FrequentItems,
+    },
+}
+
+impl DistinctCounter {
+    fn add(&mut self, value: &str) {
+        match self {
+            DistinctCounter::Exact(counts) => match counts.get_mut(value) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(value.to_string(), 1);
+                }
+            },
+            DistinctCounter::Approx { hll, frequent } => {
+                hll.add(value);
+                frequent.add(value);
+            }
+        }
+    }
+
+    fn finish(self, top: usize) -> (u64, Vec<TopValue>) {
+        let (distinct, counts) = match self {
+            DistinctCounter::Exact(counts) => (counts.len() as u64, counts),
+            DistinctCounter::Approx { hll, frequent } => (hll.count(), frequent.counters),
+        };
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        // 次数相同的按值排序, 保证输出稳定
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let top = counts
+            .into_iter()
+            .take(top)
+            .map(|(value, count)| TopValue { value, count })
+            .collect();
+        (distinct, top)
+    }
+}
+
+/// approximate distinct count with constant memory
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let idx = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION).leading_zeros() + 1).min(64 - HLL_PRECISION + 1) as u8;
+        self.registers[idx] = self.registers[idx].max(rank);
+    }
+
+    fn count(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // 基数较小的时候用linear counting修正
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// Misra-Gries frequent items, the counts are lower bounds of the real counts
+struct FrequentItems {
+    capacity: usize,
+    counters: HashMap<String, u64>,
+}
+
+impl FrequentItems {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if let Some(count) = self.counters.get_mut(value) {
+            *count += 1;
+        } else if self.counters.len() < self.capacity {
+            self.counters.insert(value.to_string(), 1);
+        } else {
+            self.counters.retain(|_, count| {
+                *count -= 1;
+                *count > 0
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::csv_opts::CsvReaderOpts;
+
+    #[test]
+    fn test_profile_records() -> Result<()> {
+        let opts = CsvReaderOpts::default();
+        let mut reader = build_csv_reader("assets/juventus.csv", &opts)?;
+        let header = reader.headers()?.clone();
+        let stats = profile_records(&header, reader.records(), 3, false)?;
+        assert_eq!(stats.rows, 27);
+        let kit = &stats.columns[4];
+        assert_eq!(kit.ty, "integer");
+        assert_eq!(kit.nulls, 0);
+        assert_eq!(kit.min, Some(Value::from(1)));
+        assert!(kit.mean.is_some());
+        let nationality = &stats.columns[3];
+        assert_eq!(nationality.ty, "string");
+        assert_eq!(nationality.top[0].value, "Italy");
+
+        // 超过2^53的整数不丢精度, NaN和inf不是数字, 整列是字符串
+        let data = "id,score\n9007199254740993,1.5\n9007199254740995,NaN\n-3,inf\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let header = reader.headers()?.clone();
+        let stats = profile_records(&header, reader.records(), 3, false)?;
+        let id = &stats.columns[0];
+        assert_eq!(id.ty, "integer");
+        assert_eq!(id.min, Some(Value::from(-3)));
+        assert_eq!(id.max, Some(Value::from(9007199254740995_i64)));
+        let score = &stats.columns[1];
+        assert_eq!(score.ty, "string");
+        assert_eq!(score.mean, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..10000 {
+            hll.add(&i.to_string());
+            hll.add(&i.to_string());
+        }
+        let count = hll.count() as f64;
+        assert!((count - 10000.0).abs() / 10000.0 < 0.05);
+    }
+}
@@ -0,0 +1,377 @@
+use super::{build_csv_reader, parse_number, render_table, value_to_cell};
+use crate::cli::csv_opts::{ColumnType, CsvValidateOpts, ReportFormat};
+use anyhow::Result;
+use csv::StringRecord;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fs};
+
+/// the schema file of `rcli csv validate`, eg:
+/// ```yaml
+/// columns:
+///   - name: Kit Number
+///     type: integer
+///     required: true
+///     min: 1
+///     max: 99
+///     unique: true
+/// ```
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvSchema {
+    pub columns: Vec<ColumnSchema>,
+    /// columns not declared in the schema are violations
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strict: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    pub name: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub ty: Option<ColumnType>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unique: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// line number in the file, the header is line 1
+    pub row: u64,
+    /// 1-based column number, none for a missing column
+    pub column: Option<usize>,
+    pub name: String,
+    pub message: String,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// validate the csv file, return the report and the number of violations
+pub fn process_csv_validate(opts: &CsvValidateOpts) -> Result<(String, usize)> {
+    let schema: CsvSchema = serde_yaml::from_str(&fs::read_to_string(&opts.schema)?)?;
+    let mut reader = build_csv_reader(&opts.input, &opts.reader)?;
+    let header = reader.headers()?;
+    let (violations, rows) = validate_records(&schema, &header, reader.records())?;
+
+    let report = match opts.format {
+        ReportFormat::Json => serde_json::to_string_pretty(&violations)? + "\n",
+        ReportFormat::Table if violations.is_empty() => format!("all {} rows are valid\n", rows),
+        ReportFormat::Table => {
+            let headers = ["row", "column", "name", "message"].map(String::from);
+            let rows = violations
+                .iter()
+                .map(|v| {
+                    vec![
+                        v.row.to_string(),
+                        v.column.map(|c| c.to_string()).unwrap_or_default(),
+                        v.name.clone(),
+                        v.message.clone(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            render_table(&headers, &rows, None)
+        }
+    };
+    Ok((report, violations.len()))
+}
+
+/// check every record against the schema, return the violations and the number of rows
+pub fn validate_records(
+    schema: &CsvSchema,
+    header: &StringRecord,
+    records: impl Iterator<Item = csv::Result<StringRecord>>,
+) -> Result<(Vec<Violation>, u64)> {
+    let mut violations = vec![];
+    let mut rules = vec![];
+    for column in &schema.columns {
+        match header.iter().position(|h| h == column.name) {
+            Some(idx) => rules.push(ColumnRule::new(idx, column)?),
+            None if column.required => violations.push(Violation {
+                row: 1,
+                column: None,
+                name: column.name.clone(),
+                message: "required column is missing".to_string(),
+            }),
+            None => {}
+        }
+    }
+    if schema.strict {
+        for (idx, name) in header.iter().enumerate() {
+            if !schema.columns.iter().any(|c| c.name == name) {
+                violations.push(Violation {
+                    row: 1,
+                    column: Some(idx + 1),
+                    name: name.to_string(),
+                    message: "column is not declared in the schema".to_string(),
+                });
+            }
+        }
+    }
+
+    let mut rows = 0;
+    for record in records {
+        rows += 1;
+        // 列数不对或者解析失败的行也是违规, 继续检查后面的行, 只有读文件失败才中止
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                violations.push(Violation {
+                    row: e.position().map_or(rows + 1, |p| p.line()),
+                    column: None,
+                    name: String::new(),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(rows + 1, |p| p.line());
+        for rule in rules.iter_mut() {
+            let value = record.get(rule.idx).unwrap_or_default();
+            for message in rule.check(value, line) {
+                violations.push(Violation {
+                    row: line,
+                    column: Some(rule.idx + 1),
+                    name: rule.schema.name.clone(),
+                    message,
+                });
+            }
+        }
+    }
+    Ok((violations, rows))
+}
+
+struct ColumnRule<'a> {
+    idx: usize,
+    schema: &'a ColumnSchema,
+    regex: Option<Regex>,
+    values: Option<Vec<String>>,
+    /// value => the line it first appears, used by `unique`
+    seen: HashMap<String, u64>,
+}
+
+impl<'a> ColumnRule<'a> {
+    fn new(idx: usize, schema: &'a ColumnSchema) -> Result<Self> {
+        let regex = schema.regex.as_deref().map(Regex::new).transpose()?;
+        let values = schema
+            .values
+            .as_ref()
+            .map(|values| values.iter().map(value_to_cell).collect());
+        Ok(Self {
+            idx,
+            schema,
+            regex,
+            values,
+            seen: HashMap::new(),
+        })
+    }
+
+    fn check(&mut self, value: &str, line: u64) -> Vec<String> {
+        let mut messages = vec![];
+        let schema = self.schema;
+        if value.trim().is_empty() {
+            if schema.required {
+                messages.push("required value is missing".to_string());
+            }
+            return messages;
+        }
+        let valid_type = schema.ty.is_none_or(|ty| is_valid_type(value, ty));
+        if let Some(ty) = schema.ty.filter(|_| !valid_type) {
+            messages.push(format!("'{}' is not a valid {}", value, ty));
+        }
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(value) {
+                messages.push(format!("'{}' does not match regex '{}'", value, regex));
+            }
+        }
+        if let Some(values) = &self.values {
+            if !values.iter().any(|v| v == value) {
+                messages.push(format!("'{}' is not one of [{}]", value, values.join(", ")));
+            }
+        }
+        // 和推断的规则一样, "NaN" "inf" 和 "007" 不是数字, 有范围的列里不是数字的值也是违规,
+        // 类型不对的时候已经报过了
+        if schema.min.is_some() || schema.max.is_some() {
+            match parse_number(value) {
+                Some(num) => {
+                    if let Some(min) = schema.min.filter(|&min| num < min) {
+                        messages.push(format!("{} is less than min {}", value, min));
+                    }
+                    if let Some(max) = schema.max.filter(|&max| num > max) {
+                        messages.push(format!("{} is greater than max {}", value, max));
+                    }
+                }
+                None if valid_type => messages.push(format!(
+                    "'{}' is not a number to compare with min/max",
+                    value
+                )),
+                None => {}
+            }
+        }
+        let len = value.chars().count();
+        if schema.min_length.is_some_and(|min| len < min) {
+            messages.push(format!("length {} is less than min_length", len));
+        }
+        if schema.max_length.is_some_and(|max| len > max) {
+            messages.push(format!("length {} is greater than max_length", len));
+        }
+        if schema.unique {
+            if let Some(first) = self.seen.get(value) {
+                messages.push(format!(
+                    "duplicate value '{}', first seen at row {}",
+                    value, first
+                ));
+            } else {
+                self.seen.insert(value.to_string(), line);
+            }
+        }
+        messages
+    }
+}
+
+/// parse the value as the declared type, unlike the inference "007" is a valid integer
+fn is_valid_type(value: &str, ty: ColumnType) -> bool {
+    let value = value.trim();
+    match ty {
+        ColumnType::String => true,
+        ColumnType::Null => value.is_empty(),
+        ColumnType::Integer => value.parse::<i64>().is_ok() || value.parse::<u64>().is_ok(),
+        // f64::from_str 也能解析 "inf" "NaN"
+        ColumnType::Float => {
+            value.bytes().any(|b| b.is_ascii_digit())
+                && value.parse::<f64>().is_ok_and(f64::is_finite)
+        }
+        ColumnType::Boolean => {
+            value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_records() -> Result<()> {
+        let schema: CsvSchema = serde_yaml::from_str(
+            r#"
+strict: true
+columns:
+  - name: name
+    required: true
+    regex: "^[a-z]+$"
+    unique: true
+  - name: age
+    type: int
+    min: 0
+    max: 150
+  - name: level
+    enum: [1, 2, 3]
+  - name: email
+    required: true
+"#,
+        )?;
+        let data = "name,age,level,extra\nbob,20,1,\nBob,x,4,\nbob,200,,\n,1,2,\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let header = reader.headers()?.clone();
+        let (violations, rows) = validate_records(&schema, &header, reader.records())?;
+        assert_eq!(rows, 4);
+        let found = violations
+            .iter()
+            .map(|v| (v.row, v.column, v.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (1, None, "email"),
+                (1, Some(4), "extra"),
+                (3, Some(1), "name"),
+                (3, Some(2), "age"),
+                (3, Some(3), "level"),
+                (4, Some(1), "name"),
+                (4, Some(2), "age"),
+                (5, Some(1), "name"),
+            ]
+        );
+        assert!(violations[5].message.starts_with("duplicate value 'bob'"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_types_and_ragged_rows() -> Result<()> {
+        let schema: CsvSchema = serde_yaml::from_str(
+            r#"
+columns:
+  - name: id
+    type: integer
+  - name: score
+    type: float
+  - name: ok
+    type: boolean
+"#,
+        )?;
+        let data = "id,score,ok\n007,1,TRUE\n1,2\n18446744073709551615,NaN,yes\n2,inf,false\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let header = reader.headers()?.clone();
+        let (violations, rows) = validate_records(&schema, &header, reader.records())?;
+        assert_eq!(rows, 4);
+        let found = violations
+            .iter()
+            .map(|v| (v.row, v.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![(3, None), (4, Some(2)), (4, Some(3)), (5, Some(2))]
+        );
+        assert!(violations[0].message.contains("2 fields"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_min_max() -> Result<()> {
+        let schema: CsvSchema = serde_yaml::from_str(
+            r#"
+columns:
+  - name: score
+    min: 0
+    max: 10
+"#,
+        )?;
+        let data = "score\n5\nabc\nNaN\ninf\n11\n\n";
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let header = reader.headers()?.clone();
+        let (violations, _) = validate_records(&schema, &header, reader.records())?;
+        let found = violations
+            .iter()
+            .map(|v| (v.row, v.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (3, "'abc' is not a number to compare with min/max"),
+                (4, "'NaN' is not a number to compare with min/max"),
+                (5, "'inf' is not a number to compare with min/max"),
+                (6, "11 is greater than max 10"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+use super::{rows_to_html, rows_to_markdown, ColumnarWriter, FinishWrite, HtmlTableWriter, Output};
+use crate::cli::csv_opts::{OutputFormat, WriterOpts};
+use csv::WriterBuilder;
+use serde_json::Value;
+use std::{collections::HashSet, io::Write};
+
+/// toml的文档不能是数组, 行放在这个key下面
+pub const TOML_ROWS_KEY: &str = "rows";
+
+/// write converted rows one by one, so that the whole file don't need to be in memory
+pub trait RowWriter {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()>;
+    /// flush the pending content, must be called after the last row
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
+/// create a row writer for the output format,
+/// yaml, toml and markdown are whole documents, they are buffered and written in `finish`,
+/// parquet and arrow are buffered and written one row group at a time
+pub fn new_row_writer(
+    format: OutputFormat,
+    delimiter: char,
+    opts: &WriterOpts,
+    writer: impl Into<Output>,
+) -> Box<dyn RowWriter> {
+    let writer = writer.into();
+    match format {
+        OutputFormat::Json => Box::new(JsonArrayWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter { writer }),
+        OutputFormat::Csv => Box::new(CsvRowWriter::new(writer, delimiter)),
+        OutputFormat::Tsv => Box::new(CsvRowWriter::new(writer, '\t')),
+        OutputFormat::Html => Box::new(HtmlTableWriter::new(writer, opts.html_class.clone())),
+        OutputFormat::Yaml | OutputFormat::Toml | OutputFormat::Markdown => {
+            Box::new(DocumentWriter {
+                format,
+                rows: vec![],
+                writer,
+            })
+        }
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            // 二进制格式不会转码
+            Box::new(ColumnarWriter::new(format, opts, Box::new(writer)))
+        }
+    }
+}
+
+/// write a pretty json array incrementally, the output is the same as `to_string_pretty`
+struct JsonArrayWriter {
+    writer: Output,
+    empty: bool,
+}
+
+impl JsonArrayWriter {
+    fn new(writer: Output) -> Self {
+        Self {
+            writer,
+            empty: true,
+        }
+    }
+}
+
+impl RowWriter for JsonArrayWriter {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        let sep = if self.empty { "[\n" } else { ",\n" };
+        self.empty = false;
+        write!(self.writer, "{}{}", sep, indented_json(row)?)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let end = if self.empty { "[]" } else { "\n]" };
+        self.writer.write_all(end.as_bytes())?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+struct NdjsonWriter {
+    writer: Output,
+}
+
+impl RowWriter for NdjsonWriter {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, row)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// the header is taken from the keys of the first row
+struct CsvRowWriter {
+    /// `finish` takes the output back from the csv writer
+    writer: Option<csv::Writer<Output>>,
+    headers: Option<Vec<String>>,
+}
+
+impl CsvRowWriter {
+    fn new(writer: Output, delimiter: char) -> Self {
+        let writer = WriterBuilder::new()
+            .delimiter(delimiter as u8)
+            .from_writer(writer);
+        Self {
+            writer: Some(writer),
+            headers: None,
+        }
+    }
+}
+
+impl RowWriter for CsvRowWriter {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("the csv writer is finished"))?;
+        // 数组形式的行没有表头, 直接写出每个值
+        if let Value::Array(values) = row {
+            writer.write_record(values.iter().map(value_to_cell))?;
+            return Ok(());
+        }
+        let headers = match &self.headers {
+            Some(headers) => headers,
+            None => {
+                let headers = collect_headers(std::slice::from_ref(row));
+                writer.write_record(&headers)?;
+                self.headers.insert(headers)
+            }
+        };
+        writer.write_record(row_cells(headers, row))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// pretty json of a row indented as an element of the json array
+fn indented_json(row: &Value) -> anyhow::Result<String> {
+    // json字符串里的换行会被转义, 所以这里的换行只会是格式化产生的
+    let content = serde_json::to_string_pretty(row)?.replace('\n', "\n  ");
+    Ok(format!("  {}", content))
+}
+
+fn row_cells<'a>(headers: &'a [String], row: &'a Value) -> impl Iterator<Item = String> + 'a {
+    headers
+        .iter()
+        .map(|h| row.get(h).map(value_to_cell).unwrap_or_default())
+}
+
+/// the rows of one chunk serialized by a worker thread, joined in order by `FragmentWriter`
+pub struct Fragment {
+    format: OutputFormat,
+    pub count: u64,
+    data: Vec<u8>,
+    csv: Option<csv::Writer<Vec<u8>>>,
+    /// csv的表头, 取自这一块第一行的key
+    header: Option<Vec<String>>,
+    /// 其他格式不能分块序列化, 只能交给FragmentWriter
+    rows: Vec<Value>,
+}
+
+impl Fragment {
+    pub fn new(format: OutputFormat, delimiter: char) -> Self {
+        let csv = match format {
+            OutputFormat::Csv => Some(delimiter),
+            OutputFormat::Tsv => Some('\t'),
+            _ => None,
+        }
+        .map(|delimiter| {
+            WriterBuilder::new()
+                .delimiter(delimiter as u8)
+                .from_writer(vec![])
+        });
+        Self {
+            format,
+            count: 0,
+            data: vec![],
+            csv,
+            header: None,
+            rows: vec![],
+        }
+    }
+
+    /// serialize the row the same way as the row writer would
+    pub fn push(&mut self, row: &Value) -> anyhow::Result<()> {
+        self.count += 1;
+        if let Some(writer) = &mut self.csv {
+            // 同一次转换的每一行key都一样, 所以每一块可以用自己第一行的key
+            match (row, &self.header) {
+                (Value::Array(values), _) => {
+                    writer.write_record(values.iter().map(value_to_cell))?
+                }
+                (_, Some(header)) => writer.write_record(row_cells(header, row))?,
+                (_, None) => {
+                    let header = self
+                        .header
+                        .insert(collect_headers(std::slice::from_ref(row)));
+                    writer.write_record(row_cells(header, row))?;
+                }
+            }
+            return Ok(());
+        }
+        match self.format {
+            OutputFormat::Json => {
+                if self.count > 1 {
+                    self.data.extend_from_slice(b",\n");
+                }
+                self.data.extend(indented_json(row)?.into_bytes());
+            }
+            OutputFormat::Ndjson => {
+                serde_json::to_writer(&mut self.data, row)?;
+                self.data.push(b'\n');
+            }
+            _ => self.rows.push(row.clone()),
+        }
+        Ok(())
+    }
+}
+
+/// join the fragments in order, the output is byte-identical to the writer of `new_row_writer`
+pub struct FragmentWriter {
+    format: OutputFormat,
+    delimiter: char,
+    sink: FragmentSink,
+    empty: bool,
+}
+
+enum FragmentSink {
+    /// json, ndjson和csv直接写出每一块序列化好的内容
+    Bytes(Output),
+    /// 其他格式把每一块的行交给row writer
+    Rows(Box<dyn RowWriter>),
+}
+
+impl FragmentWriter {
+    pub fn new(format: OutputFormat, delimiter: char, opts: &WriterOpts, writer: Output) -> Self {
+        match format {
+            OutputFormat::Json | OutputFormat::Ndjson | OutputFormat::Csv | OutputFormat::Tsv => {
+                Self {
+                    format,
+                    delimiter,
+                    sink: FragmentSink::Bytes(writer),
+                    empty: true,
+                }
+            }
+            _ => Self::from_row_writer(
+                format,
+                delimiter,
+                new_row_writer(format, delimiter, opts, writer),
+            ),
+        }
+    }
+
+    /// pass the rows of the fragments to the row writer, for the formats that aren't serialized
+    /// by the fragments
+    pub fn from_row_writer(
+        format: OutputFormat,
+        delimiter: char,
+        writer: Box<dyn RowWriter>,
+    ) -> Self {
+        Self {
+            format,
+            delimiter,
+            sink: FragmentSink::Rows(writer),
+            empty: true,
+        }
+    }
+
+    pub fn write_fragment(&mut self, mut fragment: Fragment) -> anyhow::Result<()> {
+        if let Some(writer) = fragment.csv.take() {
+            fragment.data = writer.into_inner()?;
+        }
+        if fragment.count == 0 {
+            return Ok(());
+        }
+        let writer = match &mut self.sink {
+            FragmentSink::Bytes(writer) => writer,
+            FragmentSink::Rows(writer) => {
+                for row in &fragment.rows {
+                    writer.write_row(row)?;
+                }
+                return Ok(());
+            }
+        };
+        match self.format {
+            OutputFormat::Json => {
+                let sep = if self.empty { "[\n" } else { ",\n" };
+                writer.write_all(sep.as_bytes())?;
+            }
+            OutputFormat::Csv | OutputFormat::Tsv if self.empty => {
+                if let Some(header) = &fragment.header {
+                    let delimiter = if matches!(self.format, OutputFormat::Tsv) {
+                        '\t'
+                    } else {
+                        self.delimiter
+                    };
+                    let mut writer = WriterBuilder::new()
+                        .delimiter(delimiter as u8)
+                        .from_writer(&mut *writer);
+                    writer.write_record(header)?;
+                    writer.flush()?;
+                }
+            }
+            _ => {}
+        }
+        self.empty = false;
+        writer.write_all(&fragment.data)?;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        let writer = match &mut self.sink {
+            FragmentSink::Bytes(writer) => writer,
+            FragmentSink::Rows(writer) => return writer.finish(),
+        };
+        if matches!(self.format, OutputFormat::Json) {
+            let end = if self.empty { "[]" } else { "\n]" };
+            writer.write_all(end.as_bytes())?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+struct DocumentWriter {
+    format: OutputFormat,
+    rows: Vec<Value>,
+    writer: Output,
+}
+
+impl RowWriter for DocumentWriter {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        self.rows.push(row.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let content = serialize_rows(&self.rows, self.format, ',')?;
+        self.writer.write_all(content.as_bytes())?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// serialize the converted rows to the given output format
+pub fn serialize_rows(
+    rows: &[Value],
+    format: OutputFormat,
+    delimiter: char,
+) -> anyhow::Result<String> {
+    let content = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(rows)?,
+        OutputFormat::Yaml => serde_yaml::to_string(rows)?,
+        OutputFormat::Toml => {
+            // toml的顶层必须是table, 所以把所有行放到 [[rows]] 里
+            let rows = rows.iter().filter_map(json_to_toml).collect();
+            let mut doc = toml::Table::new();
+            doc.insert(TOML_ROWS_KEY.to_string(), toml::Value::Array(rows));
+            toml::to_string(&doc)?
+        }
+        OutputFormat::Ndjson => {
+            let mut content = String::new();
+            for row in rows {
+                content.push_str(&serde_json::to_string(row)?);
+                content.push('\n');
+            }
+            content
+        }
+        OutputFormat::Csv => rows_to_csv(rows, delimiter)?,
+        OutputFormat::Tsv => rows_to_csv(rows, '\t')?,
+        OutputFormat::Html => rows_to_html(rows, None)?,
+        OutputFormat::Markdown => rows_to_markdown(rows),
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            anyhow::bail!("{} is a binary format, use the row writer", format)
+        }
+    };
+    Ok(content)
+}
+
+/// union of the keys of all rows, in the order of first appearance
+pub fn collect_headers(rows: &[Value]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut headers = vec![];
+    for row in rows.iter().filter_map(Value::as_object) {
+        for key in row.keys() {
+            if seen.insert(key.as_str()) {
+                headers.push(key.clone());
+            }
+        }
+    }
+    headers
+}
+
+/// render a json value as a csv cell, null is empty, nested value is json
+pub fn value_to_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn rows_to_csv(rows: &[Value], delimiter: char) -> anyhow::Result<String> {
+    let headers = collect_headers(rows);
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter as u8)
+        .from_writer(vec![]);
+    writer.write_record(&headers)?;
+    for row in rows {
+        writer.write_record(row_cells(&headers, row))?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// toml没有null, null的字段直接省略, 数组里的null是空字符串(和csv一样), 这样后面的元素位置不变
+fn json_to_toml(value: &Value) -> Option<toml::Value> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(toml::Value::Boolean(*b)),
+        Value::Number(n) => n
+            .as_i64()
+            .map(toml::Value::Integer)
+            .or_else(|| n.as_f64().map(toml::Value::Float)),
+        Value::String(s) => Some(toml::Value::String(s.clone())),
+        Value::Array(arr) => Some(toml::Value::Array(
+            arr.iter()
+                .map(|v| json_to_toml(v).unwrap_or_else(|| toml::Value::String(String::new())))
+                .collect(),
+        )),
+        Value::Object(map) => Some(toml::Value::Table(
+            map.iter()
+                .filter_map(|(k, v)| json_to_toml(v).map(|v| (k.clone(), v)))
+                .collect(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_rows() -> anyhow::Result<()> {
+        let rows = vec![
+            serde_json::json!({"name": "a", "age": 1, "note": null}),
+            serde_json::json!({"name": "b,c", "age": 2, "extra": true}),
+        ];
+        let content = serialize_rows(&rows, OutputFormat::Ndjson, ',')?;
+        assert_eq!(
+            content,
+            "{\"name\":\"a\",\"age\":1,\"note\":null}\n{\"name\":\"b,c\",\"age\":2,\"extra\":true}\n"
+        );
+        let content = serialize_rows(&rows, OutputFormat::Csv, ',')?;
+        assert_eq!(content, "name,age,note,extra\na,1,,\n\"b,c\",2,,true\n");
+        let content = serialize_rows(&rows, OutputFormat::Tsv, ',')?;
+        assert_eq!(
+            content,
+            "name\tage\tnote\textra\na\t1\t\t\nb,c\t2\t\ttrue\n"
+        );
+        let content = serialize_rows(&rows, OutputFormat::Toml, ',')?;
+        let doc: toml::Table = content.parse()?;
+        let rows = doc["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].get("note").is_none());
+        assert_eq!(rows[1]["age"].as_integer(), Some(2));
+
+        // 数组里的null不能省略, 否则后面的元素位置就变了
+        let rows = vec![serde_json::json!([1, null, 3])];
+        let content = serialize_rows(&rows, OutputFormat::Toml, ',')?;
+        let doc: toml::Table = content.parse()?;
+        let values = doc["rows"][0].as_array().unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[1].as_str(), Some(""));
+        assert_eq!(values[2].as_integer(), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_array_writer() -> anyhow::Result<()> {
+        let rows = vec![
+            serde_json::json!({"name": "a", "tags": {"x": 1}}),
+            serde_json::json!({"name": "b\nc", "tags": null}),
+        ];
+        let expected = serde_json::to_string_pretty(&rows)?;
+        let path = std::env::temp_dir().join("rcli_test_json_array.json");
+        let mut writer = new_row_writer(
+            OutputFormat::Json,
+            ',',
+            &WriterOpts::default(),
+            Box::new(std::fs::File::create(&path)?) as Box<dyn Write>,
+        );
+        for row in &rows {
+            writer.write_row(row)?;
+        }
+        writer.finish()?;
+        assert_eq!(std::fs::read_to_string(&path)?, expected);
+        Ok(())
+    }
+}
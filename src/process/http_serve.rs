@@ -1,3 +1,5 @@
+use super::{build_csv_reader, escape_html, HtmlTableWriter, RowWriter};
+use crate::cli::csv_opts::CsvReaderOpts;
 use anyhow::Result;
 use axum::{
     extract::{Path, State},
@@ -44,7 +46,7 @@ async fn file_handler(
     State(shared_data): State<Arc<HttpServeState>>,
     Path(path): Path<String>,
 ) -> (StatusCode, Html<String>) {
-    let p = std::path::Path::new(&shared_data.path).join(path);
+    let p = std::path::Path::new(&shared_data.path).join(&path);
     info!("access path: {:?}", p);
     if !p.exists() {
         info!("file not found: {:?}", p);
@@ -55,6 +57,25 @@ async fn file_handler(
     } else if p.is_dir() {
         let s = list_dir_items(&p);
         (StatusCode::OK, Html(s))
+    } else if p
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+    {
+        // 读文件和解析csv都是阻塞的, 不能在async的handler里直接做
+        let rendered = tokio::task::spawn_blocking(move || render_csv(&p, &path))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|rendered| rendered);
+        match rendered {
+            Ok(s) => (StatusCode::OK, Html(s)),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html(format!(
+                    "Render csv error: {}",
+                    escape_html(&format!("{:?}", e))
+                )),
+            ),
+        }
     } else {
         match tokio::fs::read_to_string(p).await {
             Ok(f) => {
@@ -100,6 +121,25 @@ fn list_dir_items(p: &PathBuf) -> String {
     s
 }
 
+/// render the csv file as a html table, the same as `rcli csv --format html`.
+/// the heading is the request path `name`, the path on the server is not exposed
+fn render_csv(p: &std::path::Path, name: &str) -> Result<String> {
+    let path = p
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("invalid path: {:?}", p))?;
+    let mut reader = build_csv_reader(path, &CsvReaderOpts::default())?;
+    // 直接写csv的记录, 不经过json object, 重复的列名也会保留
+    let mut content = vec![];
+    let mut writer = HtmlTableWriter::new(&mut content, Some("csv".to_string()));
+    writer.write_header(&reader.headers()?.iter().collect::<Vec<_>>())?;
+    for record in reader.records() {
+        writer.write_cells(&record?.iter().collect::<Vec<_>>())?;
+    }
+    writer.finish()?;
+    let table = String::from_utf8(content)?;
+    Ok(format!("<h1>{}</h1>\n{}", escape_html(name), table))
+}
+
 #[cfg(test)]
 mod test {
     use crate::process::http_serve::file_handler;
@@ -117,4 +157,31 @@ mod test {
         assert_eq!(status_code, 200);
         println!("{:?}", content);
     }
+
+    #[tokio::test]
+    async fn test_csv_file_handler() {
+        let state = super::HttpServeState {
+            path: std::path::PathBuf::from("./"),
+        };
+        let path = axum::extract::Path("fixtures/test.csv".to_string());
+        let (status_code, content) = file_handler(State(Arc::new(state)), path).await;
+        assert_eq!(status_code, 200);
+        assert!(content.0.contains("<h1>fixtures/test.csv</h1>"));
+        assert!(content.0.contains("<table class=\"csv\">"));
+        assert!(content
+            .0
+            .contains("<tr><td>v1</td><td>v2</td><td>1</td></tr>"));
+    }
+
+    #[test]
+    fn test_render_csv_duplicated_header() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("rcli_test_render.csv");
+        std::fs::write(&path, "a,a,<b>\n1,2,3\n")?;
+        let html = super::render_csv(&path, "data/<x>.csv")?;
+        assert!(html.starts_with("<h1>data/&lt;x&gt;.csv</h1>"));
+        assert!(!html.contains(std::env::temp_dir().to_str().unwrap()));
+        assert!(html.contains("<tr><th>a</th><th>a</th><th>&lt;b&gt;</th></tr>"));
+        assert!(html.contains("<tr><td>1</td><td>2</td><td>3</td></tr>"));
+        Ok(())
+    }
 }
@@ -0,0 +1,272 @@
+use super::{collect_headers, row_cell, row_columns, value_to_cell, FinishWrite, RowWriter};
+use serde_json::Value;
+use unicode_width::UnicodeWidthStr;
+
+/// escape the text for html content and attribute values
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// write the rows as a html table incrementally, the header is taken from the keys of the first row
+pub struct HtmlTableWriter<W: FinishWrite> {
+    writer: W,
+    class: Option<String>,
+    headers: Option<Vec<String>>,
+    started: bool,
+}
+
+impl<W: FinishWrite> HtmlTableWriter<W> {
+    pub fn new(writer: W, class: Option<String>) -> Self {
+        Self {
+            writer,
+            class,
+            headers: None,
+            started: false,
+        }
+    }
+
+    fn start(&mut self, headers: Option<&[String]>) -> anyhow::Result<()> {
+        self.started = true;
+        match &self.class {
+            Some(class) => writeln!(self.writer, "<table class=\"{}\">", escape_html(class))?,
+            None => writeln!(self.writer, "<table>")?,
+        }
+        if let Some(headers) = headers {
+            writeln!(self.writer, "  <thead>")?;
+            writeln!(self.writer, "    {}", html_row("th", headers))?;
+            writeln!(self.writer, "  </thead>")?;
+        }
+        writeln!(self.writer, "  <tbody>")?;
+        Ok(())
+    }
+
+    /// write the header explicitly, duplicated names are kept, it must be called before any row
+    pub fn write_header<S: AsRef<str>>(&mut self, headers: &[S]) -> anyhow::Result<()> {
+        if self.started {
+            anyhow::bail!("the header must be written before the rows");
+        }
+        let headers = headers
+            .iter()
+            .map(|h| h.as_ref().to_string())
+            .collect::<Vec<_>>();
+        self.start(Some(&headers))
+    }
+
+    /// write a row of plain cells, eg: a csv record
+    pub fn write_cells<S: AsRef<str>>(&mut self, cells: &[S]) -> anyhow::Result<()> {
+        if !self.started {
+            self.start(None)?;
+        }
+        writeln!(self.writer, "    {}", html_row("td", cells))?;
+        Ok(())
+    }
+}
+
+impl<W: FinishWrite> RowWriter for HtmlTableWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        // 数组形式的行没有表头
+        let cells = match row {
+            Value::Array(values) => values.iter().map(value_to_cell).collect::<Vec<_>>(),
+            _ => {
+                if !self.started {
+                    let headers = collect_headers(std::slice::from_ref(row));
+                    self.start(Some(&headers))?;
+                    self.headers = Some(headers);
+                }
+                self.headers
+                    .iter()
+                    .flatten()
+                    .map(|h| row.get(h).map(value_to_cell).unwrap_or_default())
+                    .collect()
+            }
+        };
+        self.write_cells(&cells)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if !self.started {
+            self.start(None)?;
+        }
+        writeln!(self.writer, "  </tbody>")?;
+        writeln!(self.writer, "</table>")?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+fn html_row<S: AsRef<str>>(tag: &str, cells: &[S]) -> String {
+    let cells = cells
+        .iter()
+        .map(|cell| format!("<{}>{}</{}>", tag, escape_html(cell.as_ref()), tag))
+        .collect::<String>();
+    format!("<tr>{}</tr>", cells)
+}
+
+/// render the rows as a html table
+pub fn rows_to_html(rows: &[Value], class: Option<&str>) -> anyhow::Result<String> {
+    let mut content = vec![];
+    let mut writer = HtmlTableWriter::new(&mut content, class.map(str::to_string));
+    for row in rows {
+        writer.write_row(row)?;
+    }
+    writer.finish()?;
+    Ok(String::from_utf8(content)?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// render the rows as a github flavored markdown table, the columns are padded to the same width,
+/// numbers are right aligned, booleans are centered and the others are left aligned.
+/// arrays (`--as-arrays`) don't have keys, their columns are `col1, col2, ...`
+pub fn rows_to_markdown(rows: &[Value]) -> String {
+    let headers = row_columns(rows);
+    if headers.is_empty() {
+        return String::new();
+    }
+    let aligns = headers
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let values = rows
+                .iter()
+                .filter_map(|row| row_cell(row, idx, name))
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                Align::Left
+            } else if values.iter().all(|value| value.is_number()) {
+                Align::Right
+            } else if values.iter().all(|value| value.is_boolean()) {
+                Align::Center
+            } else {
+                Align::Left
+            }
+        })
+        .collect::<Vec<_>>();
+    let cells = rows
+        .iter()
+        .map(|row| {
+            headers
+                .iter()
+                .enumerate()
+                .map(|(idx, name)| {
+                    let cell = row_cell(row, idx, name).map(value_to_cell);
+                    escape_markdown(&cell.unwrap_or_default())
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let headers = headers
+        .iter()
+        .map(|h| escape_markdown(h))
+        .collect::<Vec<_>>();
+    // 分隔行至少要有三个'-'
+    let mut widths = headers.iter().map(|h| h.width().max(3)).collect::<Vec<_>>();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let mut s = markdown_line(&headers, &widths, &aligns);
+    let delimiters = widths
+        .iter()
+        .zip(&aligns)
+        .map(|(width, align)| match align {
+            Align::Left => format!(":{}", "-".repeat(width - 1)),
+            Align::Center => format!(":{}:", "-".repeat(width - 2)),
+            Align::Right => format!("{}:", "-".repeat(width - 1)),
+        })
+        .collect::<Vec<_>>();
+    s.push_str(&format!("| {} |\n", delimiters.join(" | ")));
+    for row in &cells {
+        s.push_str(&markdown_line(row, &widths, &aligns));
+    }
+    s
+}
+
+fn markdown_line(cells: &[String], widths: &[usize], aligns: &[Align]) -> String {
+    let cells = cells
+        .iter()
+        .zip(widths)
+        .zip(aligns)
+        .map(|((cell, width), align)| {
+            // format!的宽度按char计算, 中文会对不齐, 所以手动补空格
+            let padding = width - cell.width();
+            match align {
+                Align::Right => format!("{}{}", " ".repeat(padding), cell),
+                Align::Center => format!(
+                    "{}{}{}",
+                    " ".repeat(padding / 2),
+                    cell,
+                    " ".repeat(padding - padding / 2)
+                ),
+                Align::Left => format!("{}{}", cell, " ".repeat(padding)),
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+/// `|` ends the cell and a newline ends the row, so they are escaped
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace(['\r', '\n'], "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rows_to_html() -> anyhow::Result<()> {
+        let rows = vec![
+            json!({"name": "<b>Tom & \"Jerry\"</b>", "age": 1}),
+            json!({"name": "b", "age": null}),
+        ];
+        assert_eq!(
+            rows_to_html(&rows, Some("table \"x\""))?,
+            "<table class=\"table &quot;x&quot;\">\n  <thead>\n    <tr><th>name</th><th>age</th></tr>\n  \
+             </thead>\n  <tbody>\n    <tr><td>&lt;b&gt;Tom &amp; &quot;Jerry&quot;&lt;/b&gt;</td><td>1</td></tr>\n    \
+             <tr><td>b</td><td></td></tr>\n  </tbody>\n</table>\n"
+        );
+        assert_eq!(
+            rows_to_html(&[], None)?,
+            "<table>\n  <tbody>\n  </tbody>\n</table>\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rows_to_markdown() {
+        let rows = vec![
+            json!({"name": "a|b", "kit": 1, "starter": true, "note": null}),
+            json!({"name": "中文", "kit": 37, "starter": false, "note": "x\ny"}),
+        ];
+        assert_eq!(
+            rows_to_markdown(&rows),
+            "| name | kit | starter | note   |\n\
+             | :--- | --: | :-----: | :----- |\n\
+             | a\\|b |   1 |  true   |        |\n\
+             | 中文 |  37 |  false  | x<br>y |\n"
+        );
+        assert_eq!(rows_to_markdown(&[]), "");
+    }
+}
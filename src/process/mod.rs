@@ -1,12 +1,58 @@
+mod csv_agg;
+mod csv_columnar;
 mod csv_convert;
+mod csv_diff;
+mod csv_dump;
+mod csv_encoding;
+mod csv_excel;
+mod csv_filter;
+mod csv_from;
+mod csv_infer;
+mod csv_infer_schema;
+mod csv_join;
+mod csv_mapping;
+mod csv_nested;
+mod csv_parallel;
+mod csv_protect;
+mod csv_reader;
+mod csv_show;
+mod csv_sql;
+mod csv_stats;
+mod csv_validate;
+mod csv_writer;
 mod gen_pass;
 mod http_serve;
+mod markup;
 mod process_base64;
 mod process_jwt;
+mod table;
 mod text;
+pub use csv_agg::*;
+pub use csv_columnar::*;
 pub use csv_convert::*;
+pub use csv_diff::*;
+pub use csv_dump::*;
+pub use csv_encoding::*;
+pub use csv_excel::*;
+pub use csv_filter::*;
+pub use csv_from::*;
+pub use csv_infer::*;
+pub use csv_infer_schema::*;
+pub use csv_join::*;
+pub use csv_mapping::*;
+pub use csv_nested::*;
+pub use csv_parallel::*;
+pub use csv_protect::*;
+pub use csv_reader::*;
+pub use csv_show::*;
+pub use csv_sql::*;
+pub use csv_stats::*;
+pub use csv_validate::*;
+pub use csv_writer::*;
 pub use gen_pass::*;
 pub use http_serve::*;
+pub use markup::*;
 pub use process_base64::*;
 pub use process_jwt::*;
+pub use table::*;
 pub use text::*;
@@ -0,0 +1,116 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// render rows as an aligned table for terminal,
+/// the column width is the display width, so CJK characters are aligned correctly,
+/// cells wider than `max_width` are truncated
+pub fn render_table(headers: &[String], rows: &[Vec<String>], max_width: Option<usize>) -> String {
+    let clean = |cell: &String| {
+        let cell = clean_cell(cell);
+        match max_width {
+            Some(max_width) => truncate_cell(&cell, max_width),
+            None => cell,
+        }
+    };
+    let headers = headers.iter().map(clean).collect::<Vec<_>>();
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(clean).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut widths = headers.iter().map(|h| h.width()).collect::<Vec<_>>();
+    for row in &rows {
+        for (idx, cell) in row.iter().enumerate() {
+            if idx < widths.len() {
+                widths[idx] = widths[idx].max(cell.width());
+            }
+        }
+    }
+
+    let border = widths
+        .iter()
+        .map(|w| "-".repeat(w + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let border = format!("+{}+\n", border);
+    let mut s = border.clone();
+    s.push_str(&render_line(&headers, &widths));
+    s.push_str(&border);
+    for row in &rows {
+        s.push_str(&render_line(row, &widths));
+    }
+    if !rows.is_empty() {
+        s.push_str(&border);
+    }
+    s
+}
+
+fn render_line(cells: &[String], widths: &[usize]) -> String {
+    let mut line = String::from("|");
+    for (idx, width) in widths.iter().enumerate() {
+        let cell = cells.get(idx).map(String::as_str).unwrap_or_default();
+        // format!的宽度按char计算, 中文会对不齐, 所以手动补空格
+        let padding = " ".repeat(width - cell.width());
+        line.push_str(&format!(" {}{} |", cell, padding));
+    }
+    line.push('\n');
+    line
+}
+
+/// truncate the cell to the display width, the last char is replaced by '…'
+pub fn truncate_cell(cell: &str, max_width: usize) -> String {
+    if cell.width() <= max_width {
+        return cell.to_string();
+    }
+    let mut width = 0;
+    let mut s = String::new();
+    for c in cell.chars() {
+        let w = c.width().unwrap_or(0);
+        // 留一个宽度给省略号
+        if width + w + 1 > max_width {
+            break;
+        }
+        width += w;
+        s.push(c);
+    }
+    if max_width > 0 {
+        s.push('…');
+    }
+    s
+}
+
+// 换行和tab会破坏表格, 替换成空格
+fn clean_cell(cell: &str) -> String {
+    cell.replace(['\n', '\r', '\t'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let headers = vec!["name".to_string(), "city".to_string()];
+        let rows = vec![
+            vec!["张三".to_string(), "北京".to_string()],
+            vec!["bob".to_string(), "NY".to_string()],
+        ];
+        let expected = "\
++------+------+
+| name | city |
++------+------+
+| 张三 | 北京 |
+| bob  | NY   |
++------+------+
+";
+        assert_eq!(render_table(&headers, &rows, None), expected);
+    }
+
+    #[test]
+    fn test_truncate_cell() {
+        assert_eq!(truncate_cell("hello", 5), "hello");
+        assert_eq!(truncate_cell("hello world", 5), "hell…");
+        // 中文宽度是2, "中文"已经占了4, 再放一个字就超了
+        assert_eq!(truncate_cell("中文字符", 5), "中文…");
+        assert_eq!(truncate_cell("中文字符", 4), "中…");
+    }
+}
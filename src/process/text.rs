@@ -3,7 +3,7 @@ use crate::{cli::text::TextSignFormat, utils::get_reader};
 use anyhow::Result;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chacha20poly1305::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng},
     ChaCha20Poly1305, Key, Nonce,
 };
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
@@ -28,11 +28,19 @@ pub trait KeyLoader {
         Self: Sized;
 }
 
+/// the first 32 bytes of the key, a shorter key is an error instead of a panic
+fn key_bytes(key: &[u8]) -> Result<&[u8; 32]> {
+    let key = key
+        .get(..32)
+        .ok_or_else(|| anyhow::anyhow!("the key must be at least 32 bytes, got {}", key.len()))?;
+    Ok(key.try_into()?)
+}
+
 pub trait KeyGenerator {
     fn generate() -> Result<Vec<Vec<u8>>>;
 }
 
-struct Blake3 {
+pub struct Blake3 {
     key: [u8; 32],
 }
 
@@ -66,9 +74,14 @@ impl Blake3 {
     }
 
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
-        let key = key.try_into()?;
-        Ok(Blake3::new(key))
+        Ok(Blake3::new(*key_bytes(key)?))
+    }
+
+    /// deterministic keyed hash of the value as hex, the same value always gets the same token
+    pub fn tokenize(&self, value: &str) -> String {
+        blake3::keyed_hash(&self.key, value.as_bytes())
+            .to_hex()
+            .to_string()
     }
 }
 
@@ -116,8 +129,7 @@ impl Ed25519Signer {
         Self { key }
     }
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
-        let key = SigningKey::from_bytes(key.try_into()?);
+        let key = SigningKey::from_bytes(key_bytes(key)?);
         Ok(Self::new(key))
     }
 }
@@ -127,9 +139,7 @@ impl Ed25519Verifier {
         Self { key }
     }
     fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
-        let key: &[u8; 32] = key.try_into()?;
-        let key = VerifyingKey::from_bytes(key)?;
+        let key = VerifyingKey::from_bytes(key_bytes(key)?)?;
         Ok(Self::new(key))
     }
 }
@@ -163,7 +173,7 @@ pub trait Encrypt {
 pub trait Decrypt {
     fn decrypt(&mut self, path: impl Read) -> Result<String>;
 }
-struct Chacha20 {
+pub struct Chacha20 {
     nonce: Nonce,
     cipher: ChaCha20Poly1305,
 }
@@ -180,11 +190,38 @@ impl Chacha20 {
         let nonce = Nonce::from_slice(NONCE).to_owned();
         Self { nonce, cipher }
     }
+
+    /// encrypt one field with a random nonce, the nonce is prepended to the ciphertext.
+    /// 固定的NONCE只能加密一条消息, 一列里有很多值, 每个值都要用不同的nonce
+    pub fn encrypt_field(&self, value: &str) -> Result<String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let mut buf = nonce.to_vec();
+        buf.extend(ciphertext);
+        Ok(URL_SAFE_NO_PAD.encode(buf))
+    }
+
+    /// decrypt a field encrypted by `encrypt_field`
+    pub fn decrypt_field(&self, value: &str) -> Result<String> {
+        let buf = URL_SAFE_NO_PAD.decode(value)?;
+        if buf.len() < NONCE.len() {
+            anyhow::bail!("ciphertext is too short");
+        }
+        let (nonce, ciphertext) = buf.split_at(NONCE.len());
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
 }
 impl KeyLoader for Chacha20 {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
         let key = fs::read(path)?;
-        let key = Key::from_slice(&key[..32]).to_owned();
+        let key = Key::from_slice(key_bytes(&key)?).to_owned();
         Ok(Self::new(key))
     }
 }
@@ -1,4 +1,7 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+};
 
 pub fn get_reader(input: &str) -> anyhow::Result<Box<dyn Read>> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -10,3 +13,13 @@ pub fn get_reader(input: &str) -> anyhow::Result<Box<dyn Read>> {
     };
     Ok(reader)
 }
+
+/// '-' 代表写到标准输出
+pub fn get_writer(output: &str) -> anyhow::Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(std::io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(output)?))
+    };
+    Ok(writer)
+}